@@ -0,0 +1,226 @@
+use crate::domain::{self, Target, TargetId};
+use anyhow::{anyhow, Context, Result};
+use async_process::Command;
+use async_std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+
+/// Lists the files which differ between the provided git revision and the working tree,
+/// including untracked files.
+pub async fn list_changed_files(dir: &Path, since: &str) -> Result<HashSet<PathBuf>> {
+    let repository_dir = run_git(dir, &["rev-parse", "--show-toplevel"]).await?;
+    let repository_dir = PathBuf::from(repository_dir.trim_end());
+
+    let changed_files = run_git(dir, &["diff", "--name-only", "-z", since, "--"]).await?;
+    let untracked_files = run_git(
+        dir,
+        &[
+            "ls-files",
+            "--others",
+            "--exclude-standard",
+            "--full-name",
+            "-z",
+        ],
+    )
+    .await?;
+
+    Ok(changed_files
+        .split('\0')
+        .chain(untracked_files.split('\0'))
+        .filter(|file| !file.is_empty())
+        .map(|file| repository_dir.join(file))
+        .collect())
+}
+
+async fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(output.stdout.as_slice()).to_string())
+    } else {
+        Err(anyhow!(
+            "git {} returned {}: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(output.stderr.as_slice()).trim_end()
+        ))
+    }
+}
+
+/// Selects the targets whose input files contain at least one of the changed files.
+///
+/// A target is also affected if any of its dependencies is affected.
+pub fn find_affected_targets(
+    targets: &HashMap<TargetId, Target>,
+    candidate_ids: &[TargetId],
+    changed_files: &HashSet<PathBuf>,
+) -> Vec<TargetId> {
+    fn is_affected(
+        target_id: &TargetId,
+        targets: &HashMap<TargetId, Target>,
+        changed_files: &HashSet<PathBuf>,
+        memo: &mut HashMap<TargetId, bool>,
+    ) -> bool {
+        if let Some(&affected) = memo.get(target_id) {
+            return affected;
+        }
+
+        let target = &targets[target_id];
+        let affected = target
+            .watched_input()
+            .map(|input| input_contains_any(&input, changed_files))
+            .unwrap_or(false)
+            || target
+                .requested_dependencies()
+                .any(|dependency_id| is_affected(dependency_id, targets, changed_files, memo));
+
+        memo.insert(target_id.clone(), affected);
+        affected
+    }
+
+    let mut memo = HashMap::new();
+    let mut affected_targets = candidate_ids
+        .iter()
+        .filter(|&target_id| is_affected(target_id, targets, changed_files, &mut memo))
+        .cloned()
+        .collect::<Vec<_>>();
+    affected_targets.sort_by_key(ToString::to_string);
+    affected_targets.dedup();
+    affected_targets
+}
+
+fn input_contains_any(input: &domain::Resources, files: &HashSet<PathBuf>) -> bool {
+    input.files.iter().any(|resource| {
+        files.iter().any(|file| {
+            resource.paths.iter().any(|path| file.starts_with(path))
+                && domain::matches_extensions(file.as_path().into(), &resource.extensions)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_affected_targets;
+    use crate::domain::{
+        AggregateTarget, BuildTarget, Condition, FilesResource, HashAlgorithm, Resources, Script,
+        Target, TargetId, TargetMetadata,
+    };
+    use async_std::path::PathBuf;
+    use std::collections::{HashMap, HashSet};
+
+    #[test]
+    fn test_find_affected_targets_by_input_files() {
+        let targets = build_targets(vec![
+            build_target("api_test", "/repo/api", vec![]),
+            build_target("webapp_test", "/repo/webapp", vec![]),
+        ]);
+
+        let affected = find_affected_targets(
+            &targets,
+            &build_target_ids(vec!["api_test", "webapp_test"]),
+            &build_files(vec!["/repo/api/src/main.rs"]),
+        );
+
+        assert_eq!(affected, build_target_ids(vec!["api_test"]));
+    }
+
+    #[test]
+    fn test_find_affected_targets_through_dependencies() {
+        let targets = build_targets(vec![
+            build_target("api_build", "/repo/api", vec![]),
+            build_target("deploy", "/repo/deploy", vec!["api_build"]),
+            aggregate_target("all", vec!["deploy"]),
+        ]);
+
+        let affected = find_affected_targets(
+            &targets,
+            &build_target_ids(vec!["all", "api_build", "deploy"]),
+            &build_files(vec!["/repo/api/Cargo.toml"]),
+        );
+
+        assert_eq!(
+            affected,
+            build_target_ids(vec!["all", "api_build", "deploy"])
+        );
+    }
+
+    #[test]
+    fn test_find_affected_targets_without_changes() {
+        let targets = build_targets(vec![build_target("api_build", "/repo/api", vec![])]);
+
+        let affected = find_affected_targets(
+            &targets,
+            &build_target_ids(vec!["api_build"]),
+            &build_files(vec!["/repo/README.md"]),
+        );
+
+        assert!(affected.is_empty());
+    }
+
+    fn build_target_id(target_name: &str) -> TargetId {
+        TargetId {
+            project_name: None,
+            target_name: target_name.to_string(),
+        }
+    }
+
+    fn build_target_ids(target_names: Vec<&str>) -> Vec<TargetId> {
+        target_names.into_iter().map(build_target_id).collect()
+    }
+
+    fn build_files(files: Vec<&str>) -> HashSet<PathBuf> {
+        files.into_iter().map(PathBuf::from).collect()
+    }
+
+    fn build_metadata(target_name: &str, dependencies: Vec<&str>) -> TargetMetadata {
+        TargetMetadata {
+            id: build_target_id(target_name),
+            project_dir: PathBuf::from("/repo"),
+            dependencies: build_target_ids(dependencies),
+            optional_dependencies: vec![],
+            after: vec![],
+        }
+    }
+
+    fn build_target(target_name: &str, input_path: &str, dependencies: Vec<&str>) -> Target {
+        let mut input = Resources::new();
+        input.files.push(FilesResource {
+            paths: vec![PathBuf::from(input_path)],
+            extensions: None,
+        });
+
+        Target::Build(BuildTarget {
+            metadata: build_metadata(target_name, dependencies),
+            condition: Condition::default(),
+            build_script: Script::Shell(":".to_string()),
+            shell: None,
+            workdir: PathBuf::new(),
+            env_files: vec![],
+            hash_algorithm: HashAlgorithm::default(),
+            finalizers: vec![],
+            run_if_missing: false,
+            sandbox: false,
+            clean_before_build: false,
+            input,
+            output: Resources::new(),
+            required_outputs: vec![],
+        })
+    }
+
+    fn aggregate_target(target_name: &str, dependencies: Vec<&str>) -> Target {
+        Target::Aggregate(AggregateTarget {
+            metadata: build_metadata(target_name, dependencies),
+        })
+    }
+
+    fn build_targets(targets: Vec<Target>) -> HashMap<TargetId, Target> {
+        targets
+            .into_iter()
+            .map(|target| (target.id().clone(), target))
+            .collect()
+    }
+}
@@ -1,23 +1,29 @@
-use crate::domain::Target;
+use crate::domain::{Resources, Target};
 use anyhow::{Context, Result};
 use async_std::fs;
 use async_std::path::Path;
 
 pub async fn clean_target_output_paths(target: &Target) -> Result<()> {
     if let Some(output) = target.output() {
-        for resource in &output.files {
-            if resource.extensions.is_some() {
-                let resource_files =
-                    crate::fs::list_files_in_paths(&resource.paths, &resource.extensions).await;
-                for file in resource_files {
-                    fs::remove_file(&file)
-                        .await
-                        .with_context(|| format!("Failed to remove file {}", file.display()))?;
-                }
-            } else {
-                for output_path in &resource.paths {
-                    clean_path(output_path).await?;
-                }
+        clean_output_paths(output).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn clean_output_paths(output: &Resources) -> Result<()> {
+    for resource in &output.files {
+        if resource.extensions.is_some() {
+            let resource_files =
+                crate::fs::list_files_in_paths(&resource.paths, &resource.extensions).await;
+            for file in resource_files {
+                fs::remove_file(&file)
+                    .await
+                    .with_context(|| format!("Failed to remove file {}", file.display()))?;
+            }
+        } else {
+            for output_path in &resource.paths {
+                clean_path(output_path).await?;
             }
         }
     }
@@ -1,12 +1,32 @@
-use clap::{crate_name, crate_version, App, AppSettings, Arg};
+use clap::{crate_name, crate_version, App, AppSettings, Arg, ArgMatches};
+
+pub mod subcommand {
+    pub static AFFECTED: &str = "affected";
+    pub static STATE: &str = "state";
+}
+
+pub mod state_action {
+    pub static SHOW: &str = "show";
+    pub static DIFF: &str = "diff";
+}
 
 pub mod arg {
     pub static PROJECT_DIR: &str = "project_dir";
     pub static VERBOSITY: &str = "verbosity";
     pub static WATCH: &str = "watch";
     pub static CLEAN: &str = "clean";
+    pub static TRACE_IO: &str = "trace_io";
+    pub static LOCK: &str = "lock";
+    pub static TAG: &str = "tag";
+    pub static EXCLUDE_TAG: &str = "exclude_tag";
+    pub static NOTIFY: &str = "notify";
+    pub static NOTIFY_COMMAND: &str = "notify_command";
     pub static GENERATE_ZSH_COMPLETION: &str = "generate_zsh_completion";
     pub static TARGETS: &str = "targets";
+    pub static SINCE: &str = "since";
+    pub static RUN: &str = "run";
+    pub static STATE_ACTION: &str = "state_action";
+    pub static TARGET: &str = "target";
 }
 
 pub fn get_app() -> App<'static> {
@@ -34,11 +54,58 @@ pub fn get_app() -> App<'static> {
         .arg(Arg::with_name(arg::WATCH).short('w').long("watch").about(
             "Enable watch mode: rebuild targets and restart services on file system changes",
         ))
+        .arg(
+            Arg::with_name(arg::NOTIFY)
+                .long("notify")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("METHOD")
+                .possible_values(&["bell", "osc9", "osc777"])
+                .requires(arg::WATCH)
+                .about("Notify of build failures, recoveries and successes in watch mode"),
+        )
+        .arg(
+            Arg::with_name(arg::NOTIFY_COMMAND)
+                .long("notify-command")
+                .takes_value(true)
+                .value_name("COMMAND")
+                .requires(arg::WATCH)
+                .about("Command to run on build failures, recoveries and successes in watch mode"),
+        )
+        .arg(Arg::with_name(arg::TRACE_IO).long("trace-io").about(
+            "Report the files accessed by builds but missing from their input or output (Linux only, requires strace)",
+        ))
         .arg(
             Arg::with_name(arg::CLEAN)
                 .long("clean")
                 .about("Start by cleaning the target outputs"),
         )
+        .arg(
+            Arg::with_name(arg::LOCK)
+                .long("lock")
+                .takes_value(true)
+                .value_name("POLICY")
+                .possible_values(&["wait", "fail", "readonly"])
+                .default_value("wait")
+                .global(true)
+                .about("What to do when another process is writing to the '.zinoma' directory"),
+        )
+        .arg(
+            Arg::with_name(arg::TAG)
+                .long("tag")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("TAG")
+                .about("Build the targets having this tag"),
+        )
+        .arg(
+            Arg::with_name(arg::EXCLUDE_TAG)
+                .long("exclude-tag")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("TAG")
+                .about("Do not build the targets having this tag"),
+        )
         .arg(
             Arg::with_name(arg::GENERATE_ZSH_COMPLETION)
                 .long("generate-zsh-completion")
@@ -50,12 +117,91 @@ pub fn get_app() -> App<'static> {
                 .multiple(true)
                 .about("Targets to build"),
         )
+        .subcommand(
+            App::new(subcommand::AFFECTED)
+                .about("List or run the targets affected by the files changed since a git revision")
+                .arg(
+                    Arg::with_name(arg::SINCE)
+                        .long("since")
+                        .takes_value(true)
+                        .value_name("GIT_REF")
+                        .required(true)
+                        .about("Git revision to compare the working tree with"),
+                )
+                .arg(
+                    Arg::with_name(arg::RUN)
+                        .long("run")
+                        .about("Run the affected targets instead of listing them"),
+                )
+                .arg(
+                    Arg::with_name(arg::WATCH)
+                        .short('w')
+                        .long("watch")
+                        .requires(arg::RUN)
+                        .about("Run the affected targets in watch mode"),
+                )
+                .arg(
+                    Arg::with_name(arg::TARGETS)
+                        .value_name("TARGETS")
+                        .multiple(true)
+                        .about("Targets to consider (defaults to all targets)"),
+                ),
+        )
+        .subcommand(
+            App::new(subcommand::STATE)
+                .about("Inspect the state recorded upon the last successful build of a target")
+                .arg(
+                    Arg::with_name(arg::STATE_ACTION)
+                        .value_name("ACTION")
+                        .possible_values(&[state_action::SHOW, state_action::DIFF])
+                        .required(true)
+                        .about("'show' prints the recorded files, modified times, hashes and command outputs as JSON, 'diff' compares them with the current state"),
+                )
+                .arg(
+                    Arg::with_name(arg::TARGET)
+                        .value_name("TARGET")
+                        .required(true)
+                        .about("Target to inspect"),
+                ),
+        )
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::SubcommandsNegateReqs)
         .setting(AppSettings::ColoredHelp)
 }
 
+/// Parses the command line arguments with the app returned by `get_app`.
+///
+/// Targets negate the subcommands which follow them, so that targets can be named after subcommands,
+/// but options do not: `zinoma -p api state show build` inspects the state of a target of the `api` project.
+// Unused by the build script, which includes this file to generate the shell completions
+#[allow(dead_code)]
+pub fn get_matches_from<F, I, T>(get_app: F, args: I) -> ArgMatches
+where
+    F: Fn() -> App<'static>,
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    let args = args
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<std::ffi::OsString>>();
+
+    match get_app()
+        .unset_setting(AppSettings::ArgsNegateSubcommands)
+        .try_get_matches_from(args.clone())
+    {
+        Ok(arg_matches)
+            if arg_matches.subcommand_name().is_some() && !arg_matches.is_present(arg::TARGETS) =>
+        {
+            arg_matches
+        }
+        _ => get_app().get_matches_from(args),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{arg, get_app};
+    use super::{arg, get_app, get_matches_from, state_action, subcommand};
 
     #[test]
     fn test_get_app_verbosity_is_optional() {
@@ -73,6 +219,137 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_get_app_notify_accepts_multiple_occurrences() {
+        let arg_matches = get_app().get_matches_from(vec![
+            "zinoma", "-w", "--notify", "bell", "--notify", "osc9", "check", "test",
+        ]);
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::NOTIFY),
+            Some(vec!["bell".to_string(), "osc9".to_string()])
+        );
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::TARGETS),
+            Some(vec!["check".to_string(), "test".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_get_app_notify_requires_watch() {
+        get_app()
+            .try_get_matches_from(vec!["zinoma", "--notify", "bell", "check"])
+            .expect_err("Notifications should only be accepted in watch mode");
+    }
+
+    #[test]
+    fn test_get_app_affected_subcommand() {
+        let arg_matches =
+            get_app().get_matches_from(vec!["zinoma", "affected", "--since", "main", "test"]);
+        let affected_matches = arg_matches
+            .subcommand_matches(subcommand::AFFECTED)
+            .unwrap();
+        assert_eq!(affected_matches.value_of(arg::SINCE), Some("main"));
+        assert_eq!(
+            affected_matches.values_of_lossy(arg::TARGETS),
+            Some(vec!["test".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_get_app_affected_watch_requires_run() {
+        let result =
+            get_app().try_get_matches_from(vec!["zinoma", "affected", "--since", "main", "-w"]);
+        assert!(result.is_err());
+
+        let arg_matches = get_app()
+            .get_matches_from(vec!["zinoma", "affected", "--since", "main", "--run", "-w"]);
+        let affected_matches = arg_matches
+            .subcommand_matches(subcommand::AFFECTED)
+            .unwrap();
+        assert!(affected_matches.is_present(arg::WATCH));
+    }
+
+    #[test]
+    fn test_get_app_state_subcommand() {
+        let arg_matches = get_app().get_matches_from(vec!["zinoma", "state", "diff", "build"]);
+        let state_matches = arg_matches.subcommand_matches(subcommand::STATE).unwrap();
+        assert_eq!(
+            state_matches.value_of(arg::STATE_ACTION),
+            Some(state_action::DIFF)
+        );
+        assert_eq!(state_matches.value_of(arg::TARGET), Some("build"));
+    }
+
+    #[test]
+    fn test_get_app_subcommands_accept_global_options() {
+        let arg_matches = get_matches_from(
+            get_app,
+            vec!["zinoma", "-p", "api", "state", "show", "build"],
+        );
+        assert_eq!(arg_matches.value_of(arg::PROJECT_DIR), Some("api"));
+        let state_matches = arg_matches.subcommand_matches(subcommand::STATE).unwrap();
+        assert_eq!(state_matches.value_of(arg::TARGET), Some("build"));
+
+        let arg_matches = get_matches_from(
+            get_app,
+            vec!["zinoma", "-v", "-p", "api", "affected", "--since", "main"],
+        );
+        assert_eq!(arg_matches.value_of(arg::PROJECT_DIR), Some("api"));
+        assert!(arg_matches
+            .subcommand_matches(subcommand::AFFECTED)
+            .is_some());
+
+        let arg_matches = get_matches_from(get_app, vec!["zinoma", "-p", "api", "--", "state"]);
+        assert!(arg_matches.subcommand_name().is_none());
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::TARGETS),
+            Some(vec!["state".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_get_app_targets_negate_subcommands() {
+        let arg_matches = get_matches_from(get_app, vec!["zinoma", "check", "affected"]);
+        assert!(arg_matches
+            .subcommand_matches(subcommand::AFFECTED)
+            .is_none());
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::TARGETS),
+            Some(vec!["check".to_string(), "affected".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_get_app_tags_accept_multiple_occurrences() {
+        let arg_matches = get_app().get_matches_from(vec![
+            "zinoma",
+            "--tag",
+            "ci",
+            "--tag",
+            "frontend",
+            "--exclude-tag",
+            "slow",
+        ]);
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::TAG),
+            Some(vec!["ci".to_string(), "frontend".to_string()])
+        );
+        assert_eq!(
+            arg_matches.values_of_lossy(arg::EXCLUDE_TAG),
+            Some(vec!["slow".to_string()])
+        );
+        assert_eq!(arg_matches.values_of_lossy(arg::TARGETS), None);
+    }
+
+    #[test]
+    fn test_get_app_lock_defaults_to_wait() {
+        let arg_matches = get_app().get_matches_from(vec!["zinoma", "check"]);
+        assert_eq!(arg_matches.value_of(arg::LOCK), Some("wait"));
+
+        let arg_matches = get_app().get_matches_from(vec!["zinoma", "--lock", "fail", "check"]);
+        assert_eq!(arg_matches.value_of(arg::LOCK), Some("fail"));
+    }
+
     #[test]
     fn test_get_app_verbosity_accepts_multiple_occurrences() {
         let arg_matches = get_app().get_matches_from(vec!["zinoma", "-vvv"]);
@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+
+/// Replaces the `${name}` placeholders of a string with the values of the corresponding variables.
+///
+/// `$${` is an escape sequence, replaced with a literal `${`.
+pub fn interpolate<F>(value: &str, resolve: F) -> Result<String>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    substitute(value, |name| resolve(name).map(Some), true)
+}
+
+/// Replaces the `${name}` placeholders for which `resolve` returns a value.
+///
+/// Unresolved placeholders and escape sequences are kept as is, so that they can be interpolated later on.
+pub fn interpolate_partially<F>(value: &str, resolve: F) -> Result<String>
+where
+    F: Fn(&str) -> Result<Option<String>>,
+{
+    substitute(value, resolve, false)
+}
+
+fn substitute<F>(value: &str, resolve: F, unescape: bool) -> Result<String>
+where
+    F: Fn(&str) -> Result<Option<String>>,
+{
+    let mut result = String::with_capacity(value.len());
+    let mut remaining = value;
+
+    while let Some(index) = remaining.find('$') {
+        result.push_str(&remaining[..index]);
+        remaining = &remaining[index..];
+
+        if remaining.starts_with("$${") {
+            result.push_str(if unescape { "${" } else { "$${" });
+            remaining = &remaining[3..];
+        } else if remaining.starts_with("${") {
+            let end = remaining
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed placeholder in {}", value))?;
+            let name = &remaining[2..end];
+            match resolve(name)? {
+                Some(variable_value) => result.push_str(&variable_value),
+                None => result.push_str(&remaining[..=end]),
+            }
+            remaining = &remaining[end + 1..];
+        } else {
+            result.push('$');
+            remaining = &remaining[1..];
+        }
+    }
+    result.push_str(remaining);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interpolate, interpolate_partially};
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_interpolate() {
+        let result = interpolate("echo ${greeting} $USER", |name| match name {
+            "greeting" => Ok("hello".to_string()),
+            _ => Err(anyhow!("Unknown variable {}", name)),
+        });
+        assert_eq!(result.unwrap(), "echo hello $USER");
+    }
+
+    #[test]
+    fn test_interpolate_escape_sequence() {
+        let result = interpolate("echo $${greeting}", |name| {
+            Err(anyhow!("Unknown variable {}", name))
+        });
+        assert_eq!(result.unwrap(), "echo ${greeting}");
+    }
+
+    #[test]
+    fn test_interpolate_unknown_variable() {
+        interpolate("echo ${unknown}", |name| {
+            Err(anyhow!("Unknown variable {}", name))
+        })
+        .expect_err("Unknown variables should be rejected");
+    }
+
+    #[test]
+    fn test_interpolate_unclosed_placeholder() {
+        interpolate("echo ${greeting", |_| Ok("hello".to_string()))
+            .expect_err("Unclosed placeholders should be rejected");
+    }
+
+    #[test]
+    fn test_interpolate_partially() {
+        let result = interpolate_partially("${matrix.node} ${other} $${matrix.node}", |name| {
+            Ok(Some("14".to_string()).filter(|_| name == "matrix.node"))
+        });
+        assert_eq!(result.unwrap(), "14 ${other} $${matrix.node}");
+    }
+}
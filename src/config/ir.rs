@@ -1,49 +1,145 @@
+use super::interpolation::{interpolate, interpolate_partially};
 use super::yaml;
 use crate::domain::{self, TargetId};
-use anyhow::{anyhow, Result};
+use crate::fs;
+use crate::run_script;
+use crate::tags::TagFilter;
+use anyhow::{anyhow, Context, Result};
 use async_std::path::{Path, PathBuf};
 use domain::{CmdResource, FilesResource};
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 pub struct Config {
     pub root_project_name: Option<String>,
     projects: HashMap<Option<String>, (PathBuf, yaml::Project)>,
 }
 
-impl From<yaml::Config> for Config {
-    fn from(config: yaml::Config) -> Self {
-        Self {
+impl TryFrom<yaml::Config> for Config {
+    type Error = anyhow::Error;
+
+    fn try_from(config: yaml::Config) -> Result<Self> {
+        let templates = config
+            .projects
+            .values()
+            .flat_map(|project| {
+                project
+                    .templates
+                    .iter()
+                    .map(move |(template_name, template)| {
+                        let template_id = TargetId {
+                            project_name: project.name.clone(),
+                            target_name: template_name.clone(),
+                        };
+                        (template_id, template.clone())
+                    })
+            })
+            .collect::<HashMap<_, _>>();
+
+        Ok(Self {
             root_project_name: (&config.projects)[&config.root_project_dir].name.to_owned(),
             projects: config
                 .projects
                 .into_iter()
-                .map(|(project_dir, project)| (project.name.clone(), (project_dir.into(), project)))
-                .collect(),
-        }
+                .map(|(project_dir, mut project)| {
+                    let config_file_path = project_dir.join("zinoma.yml");
+                    project.targets =
+                        resolve_extended_targets(project.targets, &project.name, &templates)
+                            .with_context(|| {
+                                format!("Invalid template in {}", config_file_path.display())
+                            })?;
+                    project.targets =
+                        apply_project_defaults(project.targets, &project.shell, &project.env_file);
+                    project.targets =
+                        expand_matrix_targets(project.targets).with_context(|| {
+                            format!("Invalid matrix in {}", config_file_path.display())
+                        })?;
+                    let project_dir: PathBuf = project_dir.into();
+                    validate_workdirs(&project, &project_dir).with_context(|| {
+                        format!("Invalid workdir in {}", config_file_path.display())
+                    })?;
+                    Ok((project.name.clone(), (project_dir, project)))
+                })
+                .collect::<Result<_>>()?,
+        })
     }
 }
 
 impl Config {
-    pub fn list_all_available_target_names(&self) -> Vec<String> {
-        let mut target_names = self
-            .list_all_targets()
+    /// Selects the targets matching the provided patterns.
+    ///
+    /// `@<tag>` selects all the targets having this tag.
+    /// Each pattern should match at least one target.
+    pub fn select_targets(&self, patterns: &[String]) -> Result<Vec<TargetId>> {
+        let all_target_ids = self.list_all_targets();
+        let mut selected_target_ids: Vec<TargetId> = Vec::new();
+
+        for pattern in patterns {
+            let mut matching_target_ids = if let Some(tag) = pattern.strip_prefix('@') {
+                let tagged_target_ids = all_target_ids
+                    .iter()
+                    .filter(|&target_id| {
+                        get_tags(self.get_target(target_id))
+                            .iter()
+                            .any(|t| t == tag)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if tagged_target_ids.is_empty() {
+                    return Err(anyhow!("No target has the tag {}", tag));
+                }
+                tagged_target_ids
+            } else {
+                let pattern = domain::TargetPattern::try_parse(pattern, &self.root_project_name)?;
+
+                let matching_target_ids = match pattern.as_target_id() {
+                    Some(target_id) if all_target_ids.contains(&target_id) => vec![target_id],
+                    Some(target_id) => return Err(anyhow!("Target {} does not exist", target_id)),
+                    None => all_target_ids
+                        .iter()
+                        .filter(|&target_id| pattern.matches(target_id))
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                };
+
+                if matching_target_ids.is_empty() {
+                    return Err(anyhow!("No target matches {}", pattern));
+                }
+                matching_target_ids
+            };
+
+            matching_target_ids.sort_by_key(ToString::to_string);
+            for target_id in matching_target_ids {
+                if !selected_target_ids.contains(&target_id) {
+                    selected_target_ids.push(target_id);
+                }
+            }
+        }
+
+        Ok(selected_target_ids)
+    }
+
+    /// Keeps the candidate targets matching the tag filter.
+    ///
+    /// The targets required by the selected targets are added by `try_into_domain_targets`, whatever their tags.
+    pub fn filter_tagged_targets(
+        &self,
+        candidate_ids: &[TargetId],
+        tag_filter: &TagFilter,
+    ) -> Result<Vec<TargetId>> {
+        let selected_ids = candidate_ids
             .iter()
-            .map(ToString::to_string)
+            .filter(|&target_id| tag_filter.matches(get_tags(self.get_target(target_id))))
+            .cloned()
             .collect::<Vec<_>>();
 
-        if self.root_project_name.is_some() {
-            // Add root project targets without their namespace
-            target_names.extend(
-                self.get_project(&self.root_project_name)
-                    .targets
-                    .keys()
-                    .cloned(),
-            );
+        if selected_ids.is_empty() {
+            return Err(anyhow!("No target matches the tag filters"));
         }
 
-        target_names
+        Ok(selected_ids)
     }
 
     pub fn try_into_domain_targets(
@@ -68,7 +164,7 @@ impl Config {
                 ));
             }
 
-            let (project_dir, yaml_target) = {
+            let (project_dir, vars, hash_algorithm, yaml_target) = {
                 let (project_dir, project) = config
                     .projects
                     .get_mut(&target_id.project_name)
@@ -84,16 +180,21 @@ impl Config {
                     .remove(&target_id.target_name)
                     .ok_or_else(|| anyhow!("Target {} does not exist", target_id))?;
 
-                (project_dir.clone(), yaml_target)
+                (
+                    project_dir.clone(),
+                    project.vars.clone(),
+                    project.hash_algorithm,
+                    yaml_target,
+                )
             };
 
             let (mut target, dependencies_from_input) =
-                transform_target(target_id, yaml_target, project_dir)?;
+                transform_target(target_id, yaml_target, project_dir, &vars, hash_algorithm)?;
 
             target.extend_dependencies(&dependencies_from_input);
 
             let targets_chain = [parent_targets, &[target_id]].concat();
-            for dependency_id in target.dependencies() {
+            for dependency_id in target.requested_dependencies() {
                 add_target(domain_targets, config, dependency_id, &targets_chain)?
             }
 
@@ -111,20 +212,49 @@ impl Config {
                 };
             }
 
+            let finalizers = match &target {
+                domain::Target::Build(build_target) => build_target.finalizers.clone(),
+                _ => vec![],
+            };
+
             domain_targets.insert(target_id.clone(), target);
 
+            // Finalizers do not run before the target, hence they do not belong to its dependency chain
+            for finalizer_id in &finalizers {
+                add_target(domain_targets, config, finalizer_id, &[])
+                    .with_context(|| format!("Invalid finalizer of target {}", target_id))?
+            }
+
             Ok(())
         }
 
         let mut domain_targets = HashMap::with_capacity(root_target_ids.len());
 
+        let all_target_ids = self.list_all_targets();
+
         for target_id in root_target_ids {
             add_target(&mut domain_targets, &mut self, target_id, &[])?
         }
 
+        for target in domain_targets.values() {
+            if let Some(unknown_target_id) = target
+                .metadata()
+                .after
+                .iter()
+                .find(|&target_id| !all_target_ids.contains(target_id))
+            {
+                return Err(anyhow!("Target {} does not exist", unknown_target_id))
+                    .with_context(|| format!("Invalid after of target {}", target));
+            }
+        }
+
         Ok(domain_targets)
     }
 
+    fn get_target(&self, target_id: &TargetId) -> &yaml::Target {
+        &self.projects[&target_id.project_name].1.targets[&target_id.target_name]
+    }
+
     pub fn list_all_targets(&self) -> Vec<TargetId> {
         self.projects
             .iter()
@@ -140,62 +270,749 @@ impl Config {
             })
             .collect()
     }
-
-    fn get_project(&self, project_name: &Option<String>) -> &yaml::Project {
-        &self.projects[project_name].1
-    }
 }
 
 pub fn get_dependencies(target: &yaml::Target) -> &Vec<String> {
     &match target {
         yaml::Target::Build { dependencies, .. } => dependencies,
         yaml::Target::Service { dependencies, .. } => dependencies,
-        yaml::Target::Aggregate { dependencies } => dependencies,
+        yaml::Target::Aggregate { dependencies, .. } => dependencies,
+        yaml::Target::Extended { dependencies, .. } => dependencies,
+    }
+    .0
+}
+
+fn get_after(target: &yaml::Target) -> &Vec<String> {
+    &match target {
+        yaml::Target::Build { after, .. } => after,
+        yaml::Target::Service { after, .. } => after,
+        yaml::Target::Aggregate { after, .. } => after,
+        yaml::Target::Extended { after, .. } => after,
     }
     .0
 }
 
+fn get_optional_dependencies(target: &yaml::Target) -> &Vec<String> {
+    &match target {
+        yaml::Target::Build {
+            optional_dependencies,
+            ..
+        } => optional_dependencies,
+        yaml::Target::Service {
+            optional_dependencies,
+            ..
+        } => optional_dependencies,
+        yaml::Target::Aggregate {
+            optional_dependencies,
+            ..
+        } => optional_dependencies,
+        yaml::Target::Extended {
+            optional_dependencies,
+            ..
+        } => optional_dependencies,
+    }
+    .0
+}
+
+fn get_tags(target: &yaml::Target) -> &Vec<String> {
+    &match target {
+        yaml::Target::Build { tags, .. } => tags,
+        yaml::Target::Service { tags, .. } => tags,
+        yaml::Target::Aggregate { tags, .. } => tags,
+        yaml::Target::Extended { tags, .. } => tags,
+    }
+    .0
+}
+
+fn get_matrix(target: &yaml::Target) -> &BTreeMap<String, Vec<yaml::MatrixValue>> {
+    &match target {
+        yaml::Target::Build { matrix, .. } => matrix,
+        yaml::Target::Service { matrix, .. } => matrix,
+        yaml::Target::Aggregate { matrix, .. } => matrix,
+        yaml::Target::Extended { matrix, .. } => matrix,
+    }
+    .0
+}
+
+/// Replaces the targets extending a template with the result of their merge with the template.
+fn resolve_extended_targets(
+    targets: HashMap<String, yaml::Target>,
+    project_name: &Option<String>,
+    templates: &HashMap<TargetId, yaml::Template>,
+) -> Result<HashMap<String, yaml::Target>> {
+    targets
+        .into_iter()
+        .map(|(target_name, target)| match target {
+            yaml::Target::Extended {
+                extends,
+                dependencies,
+                after,
+                optional_dependencies,
+                matrix,
+                tags,
+                when,
+                shell,
+                workdir,
+                env_file,
+                build,
+                service,
+                finally,
+                run_if_missing,
+                sandbox,
+                clean_before_build,
+                input,
+                output,
+            } => {
+                let template_id = TargetId::try_parse(&extends, project_name)?;
+                let template = templates
+                    .get(&template_id)
+                    .ok_or_else(|| anyhow!("Template {} does not exist", template_id))?
+                    .clone();
+
+                let dependencies =
+                    yaml::Dependencies([template.dependencies.0, dependencies.0].concat());
+                let after = yaml::Dependencies([template.after.0, after.0].concat());
+                let optional_dependencies = yaml::Dependencies(
+                    [template.optional_dependencies.0, optional_dependencies.0].concat(),
+                );
+                let tags = yaml::Tags([template.tags.0, tags.0].concat());
+                let when = when.or(template.when);
+                let shell = shell.or(template.shell);
+                let workdir = workdir.or(template.workdir);
+                let env_file = [template.env_file, env_file].concat();
+                let finally = yaml::Dependencies([template.finally.0, finally.0].concat());
+                let run_if_missing = run_if_missing.unwrap_or(template.run_if_missing);
+                let sandbox = sandbox.unwrap_or(template.sandbox);
+                let clean_before_build = clean_before_build.unwrap_or(template.clean_before_build);
+                let input = yaml::InputResources([template.input.0, input.0].concat());
+                let output = yaml::OutputResources([template.output.0, output.0].concat());
+
+                let target = match (build.or(template.build), service.or(template.service)) {
+                    (Some(_), Some(_)) => {
+                        return Err(anyhow!(
+                            "Target {} can not have both a build and a service script",
+                            target_name
+                        ))
+                    }
+                    (Some(build), None) => yaml::Target::Build {
+                        dependencies,
+                        after,
+                        optional_dependencies,
+                        matrix,
+                        tags,
+                        when,
+                        shell,
+                        workdir,
+                        env_file,
+                        build,
+                        finally,
+                        run_if_missing,
+                        sandbox,
+                        clean_before_build,
+                        input,
+                        output,
+                    },
+                    (None, _)
+                        if !finally.0.is_empty()
+                            || run_if_missing
+                            || sandbox
+                            || clean_before_build =>
+                    {
+                        return Err(anyhow!(
+                            "Target {} has build options but no build script",
+                            target_name
+                        ))
+                    }
+                    (None, Some(service)) if output.0.is_empty() => yaml::Target::Service {
+                        dependencies,
+                        after,
+                        optional_dependencies,
+                        matrix,
+                        tags,
+                        when,
+                        shell,
+                        workdir,
+                        env_file,
+                        service,
+                        input,
+                    },
+                    (None, Some(_)) => {
+                        return Err(anyhow!(
+                            "Service target {} can not have output resources",
+                            target_name
+                        ))
+                    }
+                    (None, None) if when.is_some() => {
+                        return Err(anyhow!(
+                            "Target {} has a condition but neither a build nor a service script",
+                            target_name
+                        ))
+                    }
+                    (None, None) if input.0.is_empty() && output.0.is_empty() => {
+                        yaml::Target::Aggregate {
+                            dependencies,
+                            after,
+                            optional_dependencies,
+                            matrix,
+                            tags,
+                        }
+                    }
+                    (None, None) => {
+                        return Err(anyhow!(
+                            "Target {} has input or output resources but neither a build nor a service script",
+                            target_name
+                        ))
+                    }
+                };
+
+                Ok((target_name, target))
+            }
+            target => Ok((target_name, target)),
+        })
+        .collect()
+}
+
+/// Expands each target defining a matrix into one target per combination of values.
+///
+/// The original target is replaced by an aggregate of all the expanded targets.
+fn expand_matrix_targets(
+    targets: HashMap<String, yaml::Target>,
+) -> Result<HashMap<String, yaml::Target>> {
+    let mut expanded_targets = HashMap::with_capacity(targets.len());
+
+    for (target_name, target) in targets {
+        let matrix = get_matrix(&target);
+        if matrix.is_empty() {
+            expanded_targets.insert(target_name, target);
+            continue;
+        }
+
+        let combinations = list_matrix_combinations(matrix)
+            .with_context(|| format!("Invalid matrix for target {}", target_name))?;
+
+        let mut expansion_names = Vec::with_capacity(combinations.len());
+        for combination in combinations {
+            let expansion_name = format!(
+                "{}[{}]",
+                target_name,
+                itertools::join(
+                    combination
+                        .iter()
+                        .map(|(variable, value)| format!("{}={}", variable, value)),
+                    ","
+                )
+            );
+            let expansion = try_map_target_strings(target.clone(), &|value| {
+                interpolate_matrix_variables(value, &combination)
+            })
+            .with_context(|| format!("Failed to expand target {}", expansion_name))?;
+
+            expanded_targets.insert(expansion_name.clone(), expansion);
+            expansion_names.push(expansion_name);
+        }
+
+        expanded_targets.insert(
+            target_name,
+            yaml::Target::Aggregate {
+                dependencies: yaml::Dependencies(expansion_names),
+                after: yaml::Dependencies::default(),
+                optional_dependencies: yaml::Dependencies::default(),
+                matrix: yaml::Matrix::default(),
+                tags: yaml::Tags::default(),
+            },
+        );
+    }
+
+    Ok(expanded_targets)
+}
+
+fn list_matrix_combinations(
+    matrix: &BTreeMap<String, Vec<yaml::MatrixValue>>,
+) -> Result<Vec<Vec<(String, String)>>> {
+    matrix
+        .iter()
+        .try_fold(vec![vec![]], |combinations, (variable, values)| {
+            if !yaml::is_valid_matrix_variable_name(variable) {
+                return Err(anyhow!("{} is not a valid matrix variable name", variable));
+            }
+            if values.is_empty() {
+                return Err(anyhow!("Matrix variable {} has no value", variable));
+            }
+            if let Some(invalid_value) = values
+                .iter()
+                .map(|value| value.to_string())
+                .find(|value| !yaml::is_valid_matrix_value(value))
+            {
+                return Err(anyhow!(
+                    "{} is not a valid value for matrix variable {}",
+                    invalid_value,
+                    variable
+                ));
+            }
+
+            Ok(combinations
+                .iter()
+                .flat_map(|combination| {
+                    values.iter().map(move |value| {
+                        let mut combination: Vec<(String, String)> = combination.clone();
+                        combination.push((variable.clone(), value.to_string()));
+                        combination
+                    })
+                })
+                .collect())
+        })
+}
+
+fn interpolate_matrix_variables(value: &str, combination: &[(String, String)]) -> Result<String> {
+    interpolate_partially(value, |name| match name.strip_prefix("matrix.") {
+        Some(variable) => combination
+            .iter()
+            .find(|(name, _)| name == variable)
+            .map(|(_, value)| Some(value.clone()))
+            .ok_or_else(|| anyhow!("Unknown matrix variable {}", variable)),
+        None => Ok(None),
+    })
+}
+
+/// Resolves the value of a variable.
+///
+/// Unknown variables are rejected: shell expansions such as `${HOME}` should be escaped as `$${HOME}`.
+fn resolve_variable(
+    name: &str,
+    target_id: &TargetId,
+    project_dir: &Path,
+    vars: &HashMap<String, String>,
+) -> Result<String> {
+    if let Some(env_var_name) = name.strip_prefix("env:") {
+        return std::env::var(env_var_name)
+            .with_context(|| format!("Environment variable {} is not defined", env_var_name));
+    }
+
+    match name {
+        "project_dir" => Ok(project_dir.to_string_lossy().to_string()),
+        "target_name" => Ok(target_id.target_name.clone()),
+        _ => vars.get(name).cloned().ok_or_else(|| {
+            anyhow!(
+                "Unknown variable {} (a shell expansion should be escaped as $${{{}}})",
+                name,
+                name
+            )
+        }),
+    }
+}
+
+/// Applies a transformation to all the strings of a target definition
+/// (scripts, dependencies, and input and output resources).
+fn try_map_target_strings<F>(target: yaml::Target, f: &F) -> Result<yaml::Target>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    let map_vec = |values: Vec<String>| values.iter().map(|value| f(value)).collect::<Result<_>>();
+    let map_dependencies =
+        |dependencies: yaml::Dependencies| map_vec(dependencies.0).map(yaml::Dependencies);
+    let map_input = |input: yaml::InputResources| {
+        input
+            .0
+            .into_iter()
+            .map(|resource| match resource {
+                yaml::InputResource::DependencyOutput(id) => {
+                    f(&id).map(yaml::InputResource::DependencyOutput)
+                }
+                yaml::InputResource::Files { paths, extensions } => {
+                    Ok(yaml::InputResource::Files {
+                        paths: map_vec(paths)?,
+                        extensions,
+                    })
+                }
+                yaml::InputResource::CmdStdout {
+                    cmd_stdout,
+                    workdir,
+                } => Ok(yaml::InputResource::CmdStdout {
+                    cmd_stdout: f(&cmd_stdout)?,
+                    workdir: workdir.map(|workdir| f(&workdir)).transpose()?,
+                }),
+            })
+            .collect::<Result<_>>()
+            .map(yaml::InputResources)
+    };
+    let map_output = |output: yaml::OutputResources| {
+        output
+            .0
+            .into_iter()
+            .map(|resource| match resource {
+                yaml::OutputResource::Files {
+                    paths,
+                    extensions,
+                    required,
+                    non_empty,
+                } => Ok(yaml::OutputResource::Files {
+                    paths: map_vec(paths)?,
+                    extensions,
+                    required,
+                    non_empty,
+                }),
+                yaml::OutputResource::CmdStdout {
+                    cmd_stdout,
+                    workdir,
+                } => Ok(yaml::OutputResource::CmdStdout {
+                    cmd_stdout: f(&cmd_stdout)?,
+                    workdir: workdir.map(|workdir| f(&workdir)).transpose()?,
+                }),
+            })
+            .collect::<Result<_>>()
+            .map(yaml::OutputResources)
+    };
+    let map_script = |script: yaml::Script| -> Result<_> {
+        Ok(match script {
+            yaml::Script::Shell(script) => yaml::Script::Shell(f(&script)?),
+            yaml::Script::Exec(args) => yaml::Script::Exec(map_vec(args)?),
+        })
+    };
+    let map_when = |when: Option<yaml::When>| {
+        when.map(|when| -> Result<_> {
+            Ok(yaml::When {
+                env: map_vec(when.env)?,
+                os: when.os,
+                cmd: when.cmd.map(|cmd| f(&cmd)).transpose()?,
+            })
+        })
+        .transpose()
+    };
+
+    Ok(match target {
+        yaml::Target::Build {
+            dependencies,
+            after,
+            optional_dependencies,
+            tags,
+            when,
+            shell,
+            workdir,
+            env_file,
+            build,
+            finally,
+            run_if_missing,
+            sandbox,
+            clean_before_build,
+            input,
+            output,
+            ..
+        } => yaml::Target::Build {
+            dependencies: map_dependencies(dependencies)?,
+            after: map_dependencies(after)?,
+            optional_dependencies: map_dependencies(optional_dependencies)?,
+            matrix: yaml::Matrix::default(),
+            tags,
+            when: map_when(when)?,
+            shell,
+            workdir: workdir.map(|workdir| f(&workdir)).transpose()?,
+            env_file: map_vec(env_file)?,
+            build: map_script(build)?,
+            finally: map_dependencies(finally)?,
+            run_if_missing,
+            sandbox,
+            clean_before_build,
+            input: map_input(input)?,
+            output: map_output(output)?,
+        },
+        yaml::Target::Service {
+            dependencies,
+            after,
+            optional_dependencies,
+            tags,
+            when,
+            shell,
+            workdir,
+            env_file,
+            service,
+            input,
+            ..
+        } => yaml::Target::Service {
+            dependencies: map_dependencies(dependencies)?,
+            after: map_dependencies(after)?,
+            optional_dependencies: map_dependencies(optional_dependencies)?,
+            matrix: yaml::Matrix::default(),
+            tags,
+            when: map_when(when)?,
+            shell,
+            workdir: workdir.map(|workdir| f(&workdir)).transpose()?,
+            env_file: map_vec(env_file)?,
+            service: map_script(service)?,
+            input: map_input(input)?,
+        },
+        yaml::Target::Aggregate {
+            dependencies,
+            after,
+            optional_dependencies,
+            tags,
+            ..
+        } => yaml::Target::Aggregate {
+            dependencies: map_dependencies(dependencies)?,
+            after: map_dependencies(after)?,
+            optional_dependencies: map_dependencies(optional_dependencies)?,
+            matrix: yaml::Matrix::default(),
+            tags,
+        },
+        yaml::Target::Extended {
+            extends,
+            dependencies,
+            after,
+            optional_dependencies,
+            tags,
+            when,
+            shell,
+            workdir,
+            env_file,
+            build,
+            service,
+            finally,
+            run_if_missing,
+            sandbox,
+            clean_before_build,
+            input,
+            output,
+            ..
+        } => yaml::Target::Extended {
+            extends,
+            dependencies: map_dependencies(dependencies)?,
+            after: map_dependencies(after)?,
+            optional_dependencies: map_dependencies(optional_dependencies)?,
+            matrix: yaml::Matrix::default(),
+            tags,
+            when: map_when(when)?,
+            shell,
+            workdir: workdir.map(|workdir| f(&workdir)).transpose()?,
+            env_file: map_vec(env_file)?,
+            build: build.map(map_script).transpose()?,
+            service: service.map(map_script).transpose()?,
+            finally: map_dependencies(finally)?,
+            run_if_missing,
+            sandbox,
+            clean_before_build,
+            input: map_input(input)?,
+            output: map_output(output)?,
+        },
+    })
+}
+
+/// Targets skipped when their outputs exist can not depend on inputs, which would not be checked.
+fn validate_run_if_missing(
+    target_id: &TargetId,
+    input: &yaml::InputResources,
+    output: &yaml::OutputResources,
+) -> Result<()> {
+    if !input.0.is_empty() {
+        return Err(anyhow!(
+            "Target {} can not have input resources, as it runs if missing",
+            target_id
+        ));
+    }
+
+    let has_output_paths = output.0.iter().any(|resource| {
+        matches!(resource, yaml::OutputResource::Files { paths, .. } if !paths.is_empty())
+    });
+    if !has_output_paths {
+        return Err(anyhow!(
+            "Target {} should have output paths, as it runs if missing",
+            target_id
+        ));
+    }
+
+    Ok(())
+}
+
+fn transform_script(script: yaml::Script) -> Result<domain::Script> {
+    match script {
+        yaml::Script::Shell(script) => Ok(domain::Script::Shell(script)),
+        yaml::Script::Exec(args) if args.is_empty() => Err(anyhow!("Script can not be empty")),
+        yaml::Script::Exec(args) => Ok(domain::Script::Exec(args)),
+    }
+}
+
+fn transform_shell(shell: Option<yaml::Shell>) -> Result<Option<domain::Shell>> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => return Ok(None),
+    };
+
+    let (mut args, explicit_args) = match shell {
+        yaml::Shell::CommandLine(command_line) => (
+            command_line
+                .split_whitespace()
+                .map(str::to_string)
+                .collect::<Vec<_>>(),
+            false,
+        ),
+        yaml::Shell::Arguments(args) => (args, true),
+    };
+
+    if args.is_empty() {
+        return Err(anyhow!("Shell can not be empty"));
+    }
+
+    let program = args.remove(0);
+    if !explicit_args {
+        args.push(run_script::script_flag(&program).to_string());
+    }
+
+    Ok(Some(domain::Shell { program, args }))
+}
+
+fn transform_hash_algorithm(hash_algorithm: yaml::HashAlgorithm) -> domain::HashAlgorithm {
+    match hash_algorithm {
+        yaml::HashAlgorithm::Blake3 => domain::HashAlgorithm::Blake3,
+        yaml::HashAlgorithm::Sha256 => domain::HashAlgorithm::Sha256,
+        yaml::HashAlgorithm::Xxh3 => domain::HashAlgorithm::Xxh3,
+    }
+}
+
+/// Applies the shell and env files of the project to its targets.
+///
+/// The shell of a target overrides the shell of the project,
+/// while the env files of a target are loaded after the env files of the project.
+fn apply_project_defaults(
+    mut targets: HashMap<String, yaml::Target>,
+    project_shell: &Option<yaml::Shell>,
+    project_env_file: &[String],
+) -> HashMap<String, yaml::Target> {
+    for target in targets.values_mut() {
+        if let yaml::Target::Build {
+            shell, env_file, ..
+        }
+        | yaml::Target::Service {
+            shell, env_file, ..
+        } = target
+        {
+            if shell.is_none() {
+                *shell = project_shell.clone();
+            }
+            *env_file = [project_env_file, env_file].concat();
+        }
+    }
+
+    targets
+}
+
+fn transform_when(when: Option<yaml::When>) -> domain::Condition {
+    when.map(|when| domain::Condition {
+        env: when
+            .env
+            .into_iter()
+            .map(|env| match env.split_once('=') {
+                Some((name, value)) => {
+                    domain::EnvCondition::Equals(name.to_string(), value.to_string())
+                }
+                None => domain::EnvCondition::NonEmpty(env),
+            })
+            .collect(),
+        os: when.os,
+        cmd: when.cmd,
+    })
+    .unwrap_or_default()
+}
+
 fn transform_target(
     target_id: &TargetId,
     yaml_target: yaml::Target,
     project_dir: PathBuf,
+    vars: &HashMap<String, String>,
+    hash_algorithm: yaml::HashAlgorithm,
 ) -> Result<(domain::Target, Vec<TargetId>)> {
+    let yaml_target = try_map_target_strings(yaml_target, &|value| {
+        interpolate(value, |name| {
+            resolve_variable(name, target_id, &project_dir, vars)
+        })
+    })
+    .with_context(|| {
+        format!(
+            "Failed to interpolate variables of target {} in {}",
+            target_id,
+            project_dir.join("zinoma.yml").display()
+        )
+    })?;
+
     let dependencies =
         TargetId::try_parse_many(get_dependencies(&yaml_target), &target_id.project_name)?;
+    let optional_dependencies = TargetId::try_parse_many(
+        get_optional_dependencies(&yaml_target),
+        &target_id.project_name,
+    )?;
+    let after = TargetId::try_parse_many(get_after(&yaml_target), &target_id.project_name)?;
 
     let metadata = domain::TargetMetadata {
         id: target_id.clone(),
         project_dir,
         dependencies,
+        optional_dependencies,
+        after,
     };
 
     match yaml_target {
         yaml::Target::Build {
+            when,
+            shell,
+            workdir,
+            env_file,
             build,
+            finally,
+            run_if_missing,
+            sandbox,
+            clean_before_build,
             input,
             output,
             ..
         } => {
+            if run_if_missing {
+                validate_run_if_missing(&metadata.id, &input, &output)?;
+            }
+            let finalizers = TargetId::try_parse_many(&finally.0, &metadata.id.project_name)?;
+            let workdir = transform_workdir(workdir, &metadata.project_dir)?;
+            let env_files = transform_env_files(env_file, &metadata.project_dir);
             let (input, dependencies_from_input) =
                 transform_input(input, &metadata.id, &metadata.project_dir)?;
-            let output = transform_output(output, &metadata.project_dir);
+            let required_outputs = transform_required_outputs(&output, &metadata.project_dir);
+            let output = transform_output(output, &metadata.project_dir)?;
             Ok((
                 domain::Target::Build(domain::BuildTarget {
                     metadata,
-                    build_script: build,
+                    condition: transform_when(when),
+                    build_script: transform_script(build)?,
+                    shell: transform_shell(shell)?,
+                    workdir,
+                    env_files,
+                    hash_algorithm: transform_hash_algorithm(hash_algorithm),
+                    finalizers,
+                    run_if_missing,
+                    sandbox,
+                    clean_before_build,
                     input,
                     output,
+                    required_outputs,
                 }),
                 dependencies_from_input,
             ))
         }
-        yaml::Target::Service { service, input, .. } => {
+        yaml::Target::Service {
+            when,
+            shell,
+            workdir,
+            env_file,
+            service,
+            input,
+            ..
+        } => {
+            let workdir = transform_workdir(workdir, &metadata.project_dir)?;
+            let env_files = transform_env_files(env_file, &metadata.project_dir);
             let (input, dependencies_from_input) =
                 transform_input(input, &metadata.id, &metadata.project_dir)?;
             Ok((
                 domain::Target::Service(domain::ServiceTarget {
                     metadata,
-                    run_script: service,
+                    condition: transform_when(when),
+                    run_script: transform_script(service)?,
+                    shell: transform_shell(shell)?,
+                    workdir,
+                    env_files,
                     input,
                 }),
                 dependencies_from_input,
@@ -205,6 +1022,11 @@ fn transform_target(
             domain::Target::Aggregate(domain::AggregateTarget { metadata }),
             vec![],
         )),
+        yaml::Target::Extended { extends, .. } => Err(anyhow!(
+            "Target {} extends {} which was not resolved",
+            target_id,
+            extends
+        )),
     }
 }
 
@@ -223,14 +1045,17 @@ fn transform_input(
                         extensions: transform_extensions(extensions),
                     })
                 }
-                yaml::InputResource::CmdStdout { cmd_stdout } => input.cmds.push(CmdResource {
+                yaml::InputResource::CmdStdout {
+                    cmd_stdout,
+                    workdir,
+                } => input.cmds.push(CmdResource {
                     cmd: cmd_stdout,
-                    dir: project_dir.to_owned(),
+                    dir: transform_workdir(workdir, project_dir)?,
                 }),
                 yaml::InputResource::DependencyOutput(id) => {
                     lazy_static! {
                         static ref RE: Regex =
-                            Regex::new(r"^((\w[-\w]*::)?\w[-\w]*)\.output$").unwrap();
+                            Regex::new(r"^((\w[-\w]*::)?\w[-\w]*(\[[^\]]*\])?)\.output$").unwrap();
                     }
                     if let Some(captures) = RE.captures(&id) {
                         let dependency_id = TargetId::try_parse(
@@ -249,25 +1074,146 @@ fn transform_input(
     )
 }
 
-fn transform_output(output: yaml::OutputResources, project_dir: &Path) -> domain::Resources {
+fn transform_output(
+    output: yaml::OutputResources,
+    project_dir: &Path,
+) -> Result<domain::Resources> {
     output
         .0
         .into_iter()
-        .fold(domain::Resources::new(), |mut acc, resource| {
+        .try_fold(domain::Resources::new(), |mut acc, resource| {
             match resource {
-                yaml::OutputResource::Files { paths, extensions } => {
-                    acc.files.push(FilesResource {
-                        paths: paths.iter().map(|path| project_dir.join(path)).collect(),
-                        extensions: transform_extensions(extensions),
-                    })
-                }
-                yaml::OutputResource::CmdStdout { cmd_stdout } => acc.cmds.push(CmdResource {
+                yaml::OutputResource::Files {
+                    paths, extensions, ..
+                } => acc.files.push(FilesResource {
+                    paths: paths.iter().map(|path| project_dir.join(path)).collect(),
+                    extensions: transform_extensions(extensions),
+                }),
+                yaml::OutputResource::CmdStdout {
+                    cmd_stdout,
+                    workdir,
+                } => acc.cmds.push(CmdResource {
                     cmd: cmd_stdout,
-                    dir: project_dir.to_owned(),
+                    dir: transform_workdir(workdir, project_dir)?,
                 }),
             }
-            acc
+            Ok(acc)
+        })
+}
+
+fn transform_required_outputs(
+    output: &yaml::OutputResources,
+    project_dir: &Path,
+) -> Vec<domain::RequiredOutput> {
+    output
+        .0
+        .iter()
+        .flat_map(|resource| match resource {
+            yaml::OutputResource::Files {
+                paths,
+                extensions,
+                required: true,
+                non_empty,
+            } => paths
+                .iter()
+                .map(|path| domain::RequiredOutput {
+                    path: project_dir.join(path),
+                    extensions: transform_extensions(extensions.clone()),
+                    non_empty: *non_empty,
+                })
+                .collect(),
+            _ => vec![],
         })
+        .collect()
+}
+
+fn transform_env_files(env_file: Vec<String>, project_dir: &Path) -> Vec<PathBuf> {
+    env_file.iter().map(|path| project_dir.join(path)).collect()
+}
+
+/// Checks the working directories of all the targets, and not only of the selected ones.
+///
+/// Working directories whose variables can not be resolved are checked once their target is selected,
+/// as this is where the interpolation errors are reported.
+fn validate_workdirs(project: &yaml::Project, project_dir: &Path) -> Result<()> {
+    let mut target_names = project.targets.keys().collect::<Vec<_>>();
+    target_names.sort();
+
+    for target_name in target_names {
+        let target_id = TargetId {
+            project_name: project.name.clone(),
+            target_name: target_name.clone(),
+        };
+        for workdir in get_workdirs(&project.targets[target_name]) {
+            let workdir = interpolate(workdir, |name| {
+                resolve_variable(name, &target_id, project_dir, &project.vars)
+            });
+            if let Ok(workdir) = workdir {
+                transform_workdir(Some(workdir), project_dir)
+                    .with_context(|| format!("Invalid target {}", target_id))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the working directories of a target and of its `cmd_stdout` resources.
+fn get_workdirs(target: &yaml::Target) -> Vec<&String> {
+    let (workdir, input, output) = match target {
+        yaml::Target::Build {
+            workdir,
+            input,
+            output,
+            ..
+        } => (workdir, input, Some(output)),
+        yaml::Target::Service { workdir, input, .. } => (workdir, input, None),
+        _ => return vec![],
+    };
+
+    let input_workdirs = input.0.iter().filter_map(|resource| match resource {
+        yaml::InputResource::CmdStdout { workdir, .. } => workdir.as_ref(),
+        _ => None,
+    });
+    let output_workdirs = output
+        .into_iter()
+        .flat_map(|output| output.0.iter())
+        .filter_map(|resource| match resource {
+            yaml::OutputResource::CmdStdout { workdir, .. } => workdir.as_ref(),
+            _ => None,
+        });
+
+    workdir
+        .iter()
+        .chain(input_workdirs)
+        .chain(output_workdirs)
+        .collect()
+}
+
+/// Resolves a working directory relative to the project directory,
+/// and checks that it exists and does not escape the project directory.
+fn transform_workdir(workdir: Option<String>, project_dir: &Path) -> Result<PathBuf> {
+    match workdir {
+        Some(workdir) => {
+            let dir = project_dir.join(&workdir);
+            let normalized_dir = fs::normalize_path(std::path::Path::new(dir.as_os_str()));
+            if !normalized_dir.starts_with(project_dir) {
+                return Err(anyhow!(
+                    "Working directory {} is outside of the project directory {}",
+                    workdir,
+                    project_dir.display()
+                ));
+            }
+            if !std::path::Path::new(dir.as_os_str()).is_dir() {
+                return Err(anyhow!(
+                    "Working directory {} does not exist",
+                    dir.display()
+                ));
+            }
+            Ok(dir)
+        }
+        None => Ok(project_dir.to_owned()),
+    }
 }
 
 fn transform_extensions(extensions: Option<Vec<String>>) -> Option<BTreeSet<String>> {
@@ -290,11 +1236,139 @@ fn transform_extensions(extensions: Option<Vec<String>>) -> Option<BTreeSet<Stri
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{
+        expand_matrix_targets, get_dependencies, resolve_extended_targets, transform_shell,
+        validate_workdirs, Config,
+    };
     use crate::config::yaml;
     use crate::domain::{self, TargetId};
+    use crate::tags::TagFilter;
     use async_std::path::PathBuf;
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_select_targets() {
+        let mut config = build_config(vec![
+            ("lint", build_empty_target()),
+            ("test", build_empty_target()),
+        ]);
+        add_project(
+            &mut config,
+            "api",
+            vec![
+                ("lint", build_empty_target()),
+                ("test", build_empty_target()),
+            ],
+        );
+        add_project(&mut config, "webapp", vec![("test", build_empty_target())]);
+
+        let select = |patterns: Vec<&str>| {
+            config
+                .select_targets(&patterns.into_iter().map(str::to_string).collect::<Vec<_>>())
+                .unwrap()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(vec!["*::test"]), vec!["api::test", "webapp::test"]);
+        assert_eq!(select(vec!["api::*"]), vec!["api::lint", "api::test"]);
+        assert_eq!(select(vec!["...::lint"]), vec!["api::lint", "lint"]);
+        assert_eq!(
+            select(vec!["test", "*::test"]),
+            vec!["test", "api::test", "webapp::test"]
+        );
+        assert_eq!(select(vec!["web*::t*t"]), vec!["webapp::test"]);
+    }
+
+    #[test]
+    fn test_select_targets_by_tag() {
+        let config = build_config(vec![
+            ("lint", build_tagged_target(vec!["ci"])),
+            ("test", build_tagged_target(vec!["ci", "slow"])),
+            ("start", build_empty_target()),
+        ]);
+
+        let selected_target_ids = config.select_targets(&["@ci".to_string()]).unwrap();
+        assert_eq!(selected_target_ids, build_target_ids(vec!["lint", "test"]));
+
+        config
+            .select_targets(&["@release".to_string()])
+            .expect_err("Tags matching no target should be rejected");
+    }
+
+    #[test]
+    fn test_filter_tagged_targets() {
+        let test_target = yaml::Target::Aggregate {
+            dependencies: yaml::Dependencies(vec!["install".to_string()]),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags(vec!["ci".to_string()]),
+        };
+        let config = build_config(vec![
+            ("lint", build_tagged_target(vec!["ci"])),
+            ("install", build_empty_target()),
+            ("test", test_target),
+            ("e2e", build_tagged_target(vec!["ci", "slow"])),
+            ("start", build_empty_target()),
+        ]);
+        let tag_filter = TagFilter {
+            included_tags: vec!["ci".to_string()],
+            excluded_tags: vec!["slow".to_string()],
+        };
+
+        let mut selected_target_ids = config
+            .filter_tagged_targets(&config.list_all_targets(), &tag_filter)
+            .unwrap();
+        selected_target_ids.sort_by_key(ToString::to_string);
+        assert_eq!(selected_target_ids, build_target_ids(vec!["lint", "test"]));
+
+        let targets = config
+            .try_into_domain_targets(&selected_target_ids)
+            .unwrap();
+        let mut target_ids = targets.keys().cloned().collect::<Vec<_>>();
+        target_ids.sort_by_key(ToString::to_string);
+        assert_eq!(
+            target_ids,
+            build_target_ids(vec!["install", "lint", "test"])
+        );
+    }
+
+    #[test]
+    fn test_filter_tagged_targets_without_match() {
+        let config = build_config(vec![("lint", build_tagged_target(vec!["ci"]))]);
+        let tag_filter = TagFilter {
+            included_tags: vec!["release".to_string()],
+            excluded_tags: vec![],
+        };
+
+        config
+            .filter_tagged_targets(&config.list_all_targets(), &tag_filter)
+            .expect_err("Tag filters matching no target should be rejected");
+    }
+
+    fn build_tagged_target(tags: Vec<&str>) -> yaml::Target {
+        yaml::Target::Aggregate {
+            dependencies: yaml::Dependencies(vec![]),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags(tags.into_iter().map(str::to_string).collect()),
+        }
+    }
+
+    #[test]
+    fn test_select_targets_without_match() {
+        let config = build_config(vec![("test", build_empty_target())]);
+
+        config
+            .select_targets(&["*::test".to_string()])
+            .expect_err("Patterns matching no target should be rejected");
+        config
+            .select_targets(&["build".to_string()])
+            .expect_err("Unknown targets should be rejected");
+    }
 
     #[test]
     fn test_try_into_domain_targets_should_return_the_requested_targets() {
@@ -328,6 +1402,8 @@ mod tests {
                 build_target_with_output(vec![yaml::OutputResource::Files {
                     paths: vec!["protos".to_string()],
                     extensions: Some(vec!["go".to_string()]),
+                    required: true,
+                    non_empty: false,
                 }]),
             ),
             (
@@ -345,7 +1421,7 @@ mod tests {
         assert_eq!(actual_targets.len(), 2);
         let target1 = find_target(&actual_targets, "target_1").unwrap();
         let target2 = find_target(&actual_targets, "target_2").unwrap();
-        assert_eq!(target2.dependencies(), &vec![target1.id().clone()]);
+        assert_eq!(target2.metadata().dependencies, vec![target1.id().clone()]);
         assert_eq!(target2.input(), target1.output());
     }
 
@@ -386,6 +1462,463 @@ mod tests {
             .expect_err("Circular dependencies should be rejected");
     }
 
+    #[test]
+    fn test_try_into_domain_targets_with_optional_dependencies() {
+        let projects = build_config(vec![
+            (
+                "target_1",
+                build_target_with_optional_dependencies(vec![], vec!["target_2"]),
+            ),
+            ("target_2", build_empty_target()),
+        ]);
+
+        let actual_targets = projects
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .unwrap();
+
+        assert_eq!(actual_targets.len(), 2);
+        let target1 = find_target(&actual_targets, "target_1").unwrap();
+        assert_eq!(
+            target1.metadata().optional_dependencies,
+            build_target_ids(vec!["target_2"])
+        );
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_should_not_request_after_targets() {
+        let projects = build_config(vec![
+            (
+                "target_1",
+                build_target_with_optional_dependencies(vec!["target_2"], vec![]),
+            ),
+            ("target_2", build_empty_target()),
+        ]);
+
+        let actual_targets = projects
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .unwrap();
+
+        assert_eq!(actual_targets.len(), 1);
+        let target1 = find_target(&actual_targets, "target_1").unwrap();
+        assert_eq!(target1.metadata().after, build_target_ids(vec!["target_2"]));
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_unknown_after_target() {
+        let projects = build_config(vec![(
+            "target_1",
+            build_target_with_optional_dependencies(vec!["target_2"], vec![]),
+        )]);
+
+        projects
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .expect_err("Unknown after targets should be rejected");
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_variables() {
+        let mut config = build_config(vec![(
+            "target_1",
+            yaml::Target::Build {
+                dependencies: yaml::Dependencies(vec![]),
+                after: yaml::Dependencies::default(),
+                optional_dependencies: yaml::Dependencies::default(),
+                matrix: yaml::Matrix::default(),
+                tags: yaml::Tags::default(),
+                when: None,
+                shell: None,
+                workdir: None,
+                env_file: vec![],
+                build: yaml::Script::Shell(
+                    "echo ${greeting} from ${target_name} $${HOME}".to_string(),
+                ),
+                finally: yaml::Dependencies::default(),
+                run_if_missing: false,
+                sandbox: false,
+                clean_before_build: false,
+                input: yaml::InputResources(vec![]),
+                output: yaml::OutputResources(vec![]),
+            },
+        )]);
+        set_vars(&mut config, vec![("greeting", "hello")]);
+
+        let actual_targets = config
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .unwrap();
+
+        match find_target(&actual_targets, "target_1").unwrap() {
+            domain::Target::Build(target) => {
+                assert_eq!(
+                    target.build_script,
+                    domain::Script::Shell("echo hello from target_1 ${HOME}".to_string())
+                )
+            }
+            _ => panic!("target_1 should be a build target"),
+        }
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_unknown_variable() {
+        let config = build_config(vec![(
+            "target_1",
+            build_target_with_input(vec![yaml::InputResource::Files {
+                paths: vec!["${src_dir}".to_string()],
+                extensions: None,
+            }]),
+        )]);
+
+        let error = config
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .expect_err("Unknown variables should be rejected");
+
+        let error = format!("{:?}", error);
+        assert!(error.contains("Failed to interpolate variables of target target_1"));
+        assert!(error.contains("Unknown variable src_dir"));
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_undefined_env_variable() {
+        let build_config = || {
+            build_config(vec![
+                ("target_1", build_empty_target()),
+                (
+                    "target_2",
+                    build_target_with_input(vec![yaml::InputResource::Files {
+                        paths: vec!["${env:ZINOMA_TEST_UNDEFINED_VARIABLE}".to_string()],
+                        extensions: None,
+                    }]),
+                ),
+            ])
+        };
+
+        build_config()
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .expect("Targets which are not selected should not be interpolated");
+        build_config()
+            .try_into_domain_targets(&build_target_ids(vec!["target_2"]))
+            .expect_err("Undefined environment variables should be rejected");
+    }
+
+    #[test]
+    fn test_validate_workdirs() {
+        let project_dir = PathBuf::from(std::env::temp_dir());
+        let build_project = |workdir: &str| {
+            let mut config = build_config(vec![
+                ("target_1", build_empty_target()),
+                (
+                    "target_2",
+                    build_target_with_input(vec![yaml::InputResource::CmdStdout {
+                        cmd_stdout: "pwd".to_string(),
+                        workdir: Some(workdir.to_string()),
+                    }]),
+                ),
+            ]);
+            config.projects.remove(&None).unwrap().1
+        };
+
+        validate_workdirs(&build_project("."), &project_dir).unwrap();
+        validate_workdirs(&build_project("${unknown}"), &project_dir)
+            .expect("Workdirs with unknown variables should be checked once selected");
+
+        let error = validate_workdirs(&build_project("../.."), &project_dir)
+            .expect_err("Workdirs outside of the project should be rejected");
+        let error = format!("{:?}", error);
+        assert!(error.contains("Invalid target target_2"));
+        assert!(error.contains("Working directory ../.. is outside of the project directory"));
+
+        validate_workdirs(&build_project("missing"), &project_dir)
+            .expect_err("Missing workdirs should be rejected, even if the target is not selected");
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_run_if_missing() {
+        let output = vec![yaml::OutputResource::Files {
+            paths: vec!["node_modules".to_string()],
+            extensions: None,
+            required: true,
+            non_empty: false,
+        }];
+        let config = build_config(vec![(
+            "target_1",
+            with_run_if_missing(build_target_with_output(output)),
+        )]);
+
+        let actual_targets = config
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .unwrap();
+
+        match find_target(&actual_targets, "target_1").unwrap() {
+            domain::Target::Build(target) => assert!(target.run_if_missing),
+            _ => panic!("target_1 should be a build target"),
+        }
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_run_if_missing_and_input() {
+        let input = vec![yaml::InputResource::Files {
+            paths: vec!["package.json".to_string()],
+            extensions: None,
+        }];
+        let config = build_config(vec![(
+            "target_1",
+            with_run_if_missing(build_target_with_input(input)),
+        )]);
+
+        let error = config
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .expect_err("Targets running if missing should not have input resources");
+
+        assert!(format!("{:?}", error).contains("can not have input resources"));
+    }
+
+    #[test]
+    fn test_try_into_domain_targets_with_run_if_missing_and_no_output_path() {
+        let config = build_config(vec![(
+            "target_1",
+            with_run_if_missing(build_target_with_output(vec![])),
+        )]);
+
+        let error = config
+            .try_into_domain_targets(&build_target_ids(vec!["target_1"]))
+            .expect_err("Targets running if missing should have output paths");
+
+        assert!(format!("{:?}", error).contains("should have output paths"));
+    }
+
+    #[test]
+    fn test_resolve_extended_targets() {
+        let mut templates = HashMap::new();
+        templates.insert(
+            TargetId {
+                project_name: Some("rust".to_string()),
+                target_name: "cargo".to_string(),
+            },
+            yaml::Template {
+                dependencies: yaml::Dependencies(vec!["fmt".to_string()]),
+                build: Some(yaml::Script::Shell("cargo build".to_string())),
+                finally: yaml::Dependencies(vec!["cleanup".to_string()]),
+                sandbox: true,
+                input: yaml::InputResources(vec![yaml::InputResource::Files {
+                    paths: vec!["src".to_string()],
+                    extensions: None,
+                }]),
+                ..Default::default()
+            },
+        );
+        let mut test_target =
+            build_extended_target("rust::cargo", Some("cargo test"), vec!["lint"]);
+        if let yaml::Target::Extended { sandbox, .. } = &mut test_target {
+            *sandbox = Some(false);
+        }
+        let mut targets = HashMap::new();
+        targets.insert("test".to_string(), test_target);
+        targets.insert(
+            "build".to_string(),
+            build_extended_target("rust::cargo", None, vec![]),
+        );
+
+        let targets = resolve_extended_targets(targets, &None, &templates).unwrap();
+
+        match &targets["test"] {
+            yaml::Target::Build {
+                dependencies,
+                build,
+                sandbox,
+                input,
+                ..
+            } => {
+                assert_eq!(dependencies.0, vec!["fmt".to_string(), "lint".to_string()]);
+                assert_eq!(build, &yaml::Script::Shell("cargo test".to_string()));
+                assert!(!sandbox);
+                assert_eq!(input.0.len(), 1);
+            }
+            _ => panic!("test should be a build target"),
+        }
+        match &targets["build"] {
+            yaml::Target::Build {
+                build,
+                finally,
+                sandbox,
+                ..
+            } => {
+                assert_eq!(build, &yaml::Script::Shell("cargo build".to_string()));
+                assert_eq!(finally.0, vec!["cleanup".to_string()]);
+                assert!(sandbox);
+            }
+            _ => panic!("build should be a build target"),
+        }
+    }
+
+    #[test]
+    fn test_resolve_extended_targets_with_unknown_template() {
+        let mut targets = HashMap::new();
+        targets.insert(
+            "test".to_string(),
+            build_extended_target("cargo", Some("cargo test"), vec![]),
+        );
+
+        resolve_extended_targets(targets, &None, &HashMap::new())
+            .expect_err("Unknown templates should be rejected");
+    }
+
+    fn build_extended_target(
+        extends: &str,
+        build: Option<&str>,
+        dependencies: Vec<&str>,
+    ) -> yaml::Target {
+        yaml::Target::Extended {
+            extends: extends.to_string(),
+            dependencies: yaml::Dependencies(
+                dependencies.into_iter().map(str::to_string).collect(),
+            ),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags::default(),
+            when: None,
+            shell: None,
+            workdir: None,
+            env_file: vec![],
+            build: build.map(|build| yaml::Script::Shell(build.to_string())),
+            service: None,
+            finally: yaml::Dependencies::default(),
+            run_if_missing: None,
+            sandbox: None,
+            clean_before_build: None,
+            input: yaml::InputResources(vec![]),
+            output: yaml::OutputResources(vec![]),
+        }
+    }
+
+    #[test]
+    fn test_transform_shell() {
+        let shell = transform_shell(Some(yaml::Shell::CommandLine(
+            "bash -euo pipefail".to_string(),
+        )))
+        .unwrap()
+        .unwrap();
+        assert_eq!(shell.program, "bash");
+        assert_eq!(shell.args, vec!["-euo", "pipefail", "-c"]);
+
+        let shell = transform_shell(Some(yaml::Shell::Arguments(vec![
+            "deno".to_string(),
+            "eval".to_string(),
+        ])))
+        .unwrap()
+        .unwrap();
+        assert_eq!(shell.program, "deno");
+        assert_eq!(shell.args, vec!["eval"]);
+
+        transform_shell(Some(yaml::Shell::Arguments(vec![])))
+            .expect_err("An empty shell should be rejected");
+    }
+
+    #[test]
+    fn test_expand_matrix_targets() {
+        let mut matrix = BTreeMap::new();
+        matrix.insert(
+            "node".to_string(),
+            vec![
+                yaml::MatrixValue::Integer(14),
+                yaml::MatrixValue::Integer(16),
+            ],
+        );
+        matrix.insert(
+            "os".to_string(),
+            vec![yaml::MatrixValue::String("linux".to_string())],
+        );
+        let mut targets = HashMap::new();
+        targets.insert(
+            "test".to_string(),
+            yaml::Target::Build {
+                dependencies: yaml::Dependencies(vec!["install[node=${matrix.node}]".to_string()]),
+                after: yaml::Dependencies::default(),
+                optional_dependencies: yaml::Dependencies::default(),
+                matrix: yaml::Matrix(matrix),
+                tags: yaml::Tags::default(),
+                when: None,
+                shell: None,
+                workdir: None,
+                env_file: vec![],
+                build: yaml::Script::Shell("nvm use ${matrix.node} && npm test".to_string()),
+                finally: yaml::Dependencies::default(),
+                run_if_missing: false,
+                sandbox: false,
+                clean_before_build: false,
+                input: yaml::InputResources(vec![]),
+                output: yaml::OutputResources(vec![]),
+            },
+        );
+
+        let targets = expand_matrix_targets(targets).unwrap();
+
+        assert_eq!(targets.len(), 3);
+        match &targets["test[node=14,os=linux]"] {
+            yaml::Target::Build {
+                dependencies,
+                build,
+                ..
+            } => {
+                assert_eq!(dependencies.0, vec!["install[node=14]".to_string()]);
+                assert_eq!(
+                    build,
+                    &yaml::Script::Shell("nvm use 14 && npm test".to_string())
+                );
+            }
+            _ => panic!("Expanded target should be a build target"),
+        }
+        assert!(targets.contains_key("test[node=16,os=linux]"));
+        assert_eq!(
+            get_dependencies(&targets["test"]),
+            &vec![
+                "test[node=14,os=linux]".to_string(),
+                "test[node=16,os=linux]".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_matrix_targets_with_unknown_variable() {
+        let mut matrix = BTreeMap::new();
+        matrix.insert("node".to_string(), vec![yaml::MatrixValue::Integer(14)]);
+        let mut targets = HashMap::new();
+        targets.insert(
+            "test".to_string(),
+            yaml::Target::Aggregate {
+                dependencies: yaml::Dependencies(vec!["install[os=${matrix.os}]".to_string()]),
+                after: yaml::Dependencies::default(),
+                optional_dependencies: yaml::Dependencies::default(),
+                matrix: yaml::Matrix(matrix),
+                tags: yaml::Tags::default(),
+            },
+        );
+
+        expand_matrix_targets(targets).expect_err("Unknown matrix variables should be rejected");
+    }
+
+    #[test]
+    fn test_expand_matrix_targets_with_invalid_value() {
+        let mut matrix = BTreeMap::new();
+        matrix.insert(
+            "dir".to_string(),
+            vec![yaml::MatrixValue::String("../src".to_string())],
+        );
+        let mut targets = HashMap::new();
+        targets.insert(
+            "test".to_string(),
+            yaml::Target::Aggregate {
+                dependencies: yaml::Dependencies::default(),
+                after: yaml::Dependencies::default(),
+                optional_dependencies: yaml::Dependencies::default(),
+                matrix: yaml::Matrix(matrix),
+                tags: yaml::Tags::default(),
+            },
+        );
+
+        expand_matrix_targets(targets)
+            .expect_err("Matrix values which cannot be part of a target name should be rejected");
+    }
+
     fn build_target_ids(names: Vec<&str>) -> Vec<TargetId> {
         names
             .iter()
@@ -405,13 +1938,47 @@ mod tests {
             dependencies: yaml::Dependencies(
                 dependencies.into_iter().map(str::to_string).collect(),
             ),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags::default(),
+        }
+    }
+
+    fn build_target_with_optional_dependencies(
+        after: Vec<&str>,
+        optional_dependencies: Vec<&str>,
+    ) -> yaml::Target {
+        yaml::Target::Aggregate {
+            dependencies: yaml::Dependencies::default(),
+            after: yaml::Dependencies(after.into_iter().map(str::to_string).collect()),
+            optional_dependencies: yaml::Dependencies(
+                optional_dependencies
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags::default(),
         }
     }
 
     fn build_target_with_input(input: Vec<yaml::InputResource>) -> yaml::Target {
         yaml::Target::Build {
             dependencies: yaml::Dependencies(vec![]),
-            build: ":".to_string(),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags::default(),
+            when: None,
+            shell: None,
+            workdir: None,
+            env_file: vec![],
+            build: yaml::Script::Shell(":".to_string()),
+            finally: yaml::Dependencies::default(),
+            run_if_missing: false,
+            sandbox: false,
+            clean_before_build: false,
             input: yaml::InputResources(input),
             output: yaml::OutputResources(vec![]),
         }
@@ -420,12 +1987,31 @@ mod tests {
     fn build_target_with_output(output: Vec<yaml::OutputResource>) -> yaml::Target {
         yaml::Target::Build {
             dependencies: yaml::Dependencies(vec![]),
-            build: ":".to_string(),
+            after: yaml::Dependencies::default(),
+            optional_dependencies: yaml::Dependencies::default(),
+            matrix: yaml::Matrix::default(),
+            tags: yaml::Tags::default(),
+            when: None,
+            shell: None,
+            workdir: None,
+            env_file: vec![],
+            build: yaml::Script::Shell(":".to_string()),
+            finally: yaml::Dependencies::default(),
+            run_if_missing: false,
+            sandbox: false,
+            clean_before_build: false,
             input: yaml::InputResources(vec![]),
             output: yaml::OutputResources(output),
         }
     }
 
+    fn with_run_if_missing(mut target: yaml::Target) -> yaml::Target {
+        if let yaml::Target::Build { run_if_missing, .. } = &mut target {
+            *run_if_missing = true;
+        }
+        target
+    }
+
     pub fn build_config(targets: Vec<(&str, yaml::Target)>) -> Config {
         let mut projects = HashMap::new();
         projects.insert(
@@ -435,6 +2021,12 @@ mod tests {
                 yaml::Project {
                     name: None,
                     imports: HashMap::new(),
+                    workspaces: vec![],
+                    vars: HashMap::new(),
+                    templates: HashMap::new(),
+                    shell: None,
+                    env_file: vec![],
+                    hash_algorithm: yaml::HashAlgorithm::default(),
                     targets: targets
                         .into_iter()
                         .map(|(k, v)| (k.to_string(), v))
@@ -449,6 +2041,23 @@ mod tests {
         }
     }
 
+    fn add_project(config: &mut Config, project_name: &str, targets: Vec<(&str, yaml::Target)>) {
+        let mut project_config = build_config(targets);
+        let (project_dir, mut project) = project_config.projects.remove(&None).unwrap();
+        project.name = Some(project_name.to_string());
+        config
+            .projects
+            .insert(project.name.clone(), (project_dir, project));
+    }
+
+    fn set_vars(config: &mut Config, vars: Vec<(&str, &str)>) {
+        let (_, project) = config.projects.get_mut(&None).unwrap();
+        project.vars = vars
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+    }
+
     fn find_target<'a>(
         targets: &'a HashMap<domain::TargetId, domain::Target>,
         target_name: &str,
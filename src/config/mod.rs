@@ -1,2 +1,3 @@
+mod interpolation;
 pub mod ir;
 pub mod yaml;
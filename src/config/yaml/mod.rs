@@ -4,8 +4,10 @@ use anyhow::{anyhow, Context, Error, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
 pub use schema::*;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fmt;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
@@ -39,6 +41,7 @@ impl Config {
                         .map(|dir| (import_name.clone(), dir))
                 })
                 .collect::<Result<Vec<_>>>()?;
+            let workspace_dirs = find_workspace_dirs(&project_dir, &project.workspaces)?;
             projects.insert(project_dir, project);
 
             for (import_name, import_dir) in import_paths {
@@ -53,10 +56,22 @@ impl Config {
                     .with_context(|| format!("Failed to import {}", &import_name))?;
             }
 
+            for workspace_dir in workspace_dirs {
+                add_project(workspace_dir.clone(), projects)
+                    .and_then(|_| match &projects[&workspace_dir].name {
+                        None => Err(anyhow!("Project cannot be imported as it has no name")),
+                        _ => Ok(()),
+                    })
+                    .with_context(|| {
+                        format!("Failed to import workspace {}", workspace_dir.display())
+                    })?;
+            }
+
             Ok(())
         }
 
         add_project(root_project_dir.clone(), &mut projects)?;
+        check_unique_project_names(&projects)?;
 
         Ok(Self {
             root_project_dir,
@@ -66,11 +81,14 @@ impl Config {
 
     fn load_project(project_dir: &Path) -> Result<Project> {
         let config_file_path = project_dir.join("zinoma.yml");
-        let config_file = File::open(&config_file_path).with_context(|| {
+        let config = std::fs::read_to_string(&config_file_path).with_context(|| {
             format!("Failed to open config file {}", config_file_path.display())
         })?;
-        let project: Project = serde_yaml::from_reader(config_file)
+        let mut project: Project = serde_yaml::from_str(&config)
+            .with_context(|| format!("Invalid format for {}", config_file_path.display()))?;
+        let raw_matrices: RawMatrices = serde_yaml::from_str(&config)
             .with_context(|| format!("Invalid format for {}", config_file_path.display()))?;
+        raw_matrices.restore(&mut project);
 
         if let Some(project_name) = &project.name {
             if !is_valid_project_name(project_name) {
@@ -78,6 +96,18 @@ impl Config {
             }
         }
 
+        if let Some(invalid_variable_name) = project
+            .vars
+            .keys()
+            .find(|&variable_name| !is_valid_variable_name(variable_name))
+        {
+            return Err(anyhow!(
+                "{} is not a valid variable name (in {})",
+                invalid_variable_name,
+                config_file_path.display()
+            ));
+        }
+
         if let Some(invalid_target_name) = project
             .targets
             .keys()
@@ -89,6 +119,17 @@ impl Config {
             ));
         }
 
+        if let Some(invalid_template_name) = project
+            .templates
+            .keys()
+            .find(|&template_name| !is_valid_target_name(template_name))
+        {
+            return Err(anyhow!(
+                "{} is not a valid template name",
+                invalid_template_name
+            ));
+        }
+
         Ok(project)
     }
 
@@ -97,6 +138,125 @@ impl Config {
     }
 }
 
+impl fmt::Display for MatrixValue {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatrixValue::Boolean(value) => write!(fmt, "{}", value),
+            MatrixValue::Integer(value) => write!(fmt, "{}", value),
+            MatrixValue::Float(value) => write!(fmt, "{}", value),
+            MatrixValue::String(value) => write!(fmt, "{}", value),
+        }
+    }
+}
+
+/// Matrix values of the targets, as they are written in the config file.
+///
+/// Targets are deserialized as an untagged enum, which buffers their fields and parses scalars:
+/// a matrix value such as `3.10` would be turned into the float `3.1`.
+#[derive(Deserialize)]
+struct RawMatrices {
+    #[serde(default)]
+    targets: HashMap<String, RawMatrix>,
+}
+
+#[derive(Deserialize)]
+struct RawMatrix {
+    #[serde(default)]
+    matrix: BTreeMap<String, Vec<RawScalar>>,
+}
+
+struct RawScalar(String);
+
+impl<'de> Deserialize<'de> for RawScalar {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawScalarVisitor;
+
+        impl<'de> de::Visitor<'de> for RawScalarVisitor {
+            type Value = RawScalar;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a scalar value")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(RawScalar(value.to_string()))
+            }
+        }
+
+        deserializer.deserialize_str(RawScalarVisitor)
+    }
+}
+
+impl RawMatrices {
+    fn restore(mut self, project: &mut Project) {
+        for (target_name, target) in project.targets.iter_mut() {
+            let matrix = match target {
+                Target::Build { matrix, .. }
+                | Target::Service { matrix, .. }
+                | Target::Aggregate { matrix, .. }
+                | Target::Extended { matrix, .. } => matrix,
+            };
+            if let Some(raw_matrix) = self.targets.remove(target_name) {
+                for (variable, raw_values) in raw_matrix.matrix {
+                    let values = raw_values
+                        .into_iter()
+                        .map(|RawScalar(value)| MatrixValue::String(value))
+                        .collect();
+                    matrix.0.insert(variable, values);
+                }
+            }
+        }
+    }
+}
+
+/// Lists the directories matching the workspace patterns which contain a `zinoma.yml` file.
+fn find_workspace_dirs(project_dir: &Path, workspaces: &[String]) -> Result<Vec<PathBuf>> {
+    let mut workspace_dirs = Vec::new();
+
+    for pattern in workspaces {
+        let full_pattern = project_dir.join(pattern);
+        let paths = glob::glob(&full_pattern.to_string_lossy())
+            .with_context(|| format!("Invalid workspace pattern {}", pattern))?;
+
+        for path in paths {
+            let path = path.with_context(|| format!("Failed to resolve workspace {}", pattern))?;
+            if path.join("zinoma.yml").is_file() {
+                let workspace_dir = canonicalize_dir(&path)?;
+                if workspace_dir != project_dir && !workspace_dirs.contains(&workspace_dir) {
+                    workspace_dirs.push(workspace_dir);
+                }
+            }
+        }
+    }
+
+    Ok(workspace_dirs)
+}
+
+fn check_unique_project_names(projects: &HashMap<PathBuf, Project>) -> Result<()> {
+    let mut project_dirs_by_name: HashMap<&str, &Path> = HashMap::new();
+
+    let mut named_projects = projects
+        .iter()
+        .filter_map(|(project_dir, project)| {
+            project.name.as_deref().map(|name| (name, project_dir))
+        })
+        .collect::<Vec<_>>();
+    named_projects.sort();
+
+    for (name, project_dir) in named_projects {
+        if let Some(other_project_dir) = project_dirs_by_name.insert(name, project_dir) {
+            return Err(anyhow!(
+                "Project name {} is used by both {} and {}",
+                name,
+                other_project_dir.join("zinoma.yml").display(),
+                project_dir.join("zinoma.yml").display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 fn canonicalize_dir(dir: &Path) -> Result<PathBuf> {
     dunce::canonicalize(dir).map_err(|e| {
         let context = if e.kind() == ErrorKind::NotFound {
@@ -122,9 +282,32 @@ pub fn is_valid_project_name(project_name: &str) -> bool {
     RE.is_match(project_name)
 }
 
+/// Variable names should not conflict with the built-in variables.
+pub fn is_valid_variable_name(variable_name: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\w+$").unwrap();
+    }
+    RE.is_match(variable_name) && !["project_dir", "target_name"].contains(&variable_name)
+}
+
+pub fn is_valid_matrix_variable_name(variable_name: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^\w+$").unwrap();
+    }
+    RE.is_match(variable_name)
+}
+
+/// Matrix values are part of the names of the expanded targets.
+pub fn is_valid_matrix_value(value: &str) -> bool {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^[-.\w]+$").unwrap();
+    }
+    RE.is_match(value)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::is_valid_target_name;
+    use super::{is_valid_matrix_value, is_valid_target_name, Project, RawMatrices, Target};
 
     #[test]
     fn test_is_valid_target_name() {
@@ -147,4 +330,45 @@ mod tests {
         );
         assert!(!is_valid_target_name(""), "A target name cannot be empty");
     }
+
+    #[test]
+    fn test_is_valid_matrix_value() {
+        assert!(is_valid_matrix_value("3.10"));
+        assert!(is_valid_matrix_value("node-14"));
+
+        assert!(!is_valid_matrix_value("src/lib"));
+        assert!(!is_valid_matrix_value("api::test"));
+        assert!(!is_valid_matrix_value("a,b"));
+        assert!(!is_valid_matrix_value(""));
+    }
+
+    #[test]
+    fn test_raw_matrices_restore() {
+        let config = r#"
+targets:
+  test:
+    matrix:
+      python: [3.10, 3.9, "3.8"]
+      debug: [true]
+    build: tox -e py${matrix.python}
+"#;
+        let mut project: Project = serde_yaml::from_str(config).unwrap();
+        let raw_matrices: RawMatrices = serde_yaml::from_str(config).unwrap();
+
+        raw_matrices.restore(&mut project);
+
+        match &project.targets["test"] {
+            Target::Build { matrix, .. } => {
+                let values = |variable: &str| {
+                    matrix.0[variable]
+                        .iter()
+                        .map(|value| value.to_string())
+                        .collect::<Vec<_>>()
+                };
+                assert_eq!(values("python"), vec!["3.10", "3.9", "3.8"]);
+                assert_eq!(values("debug"), vec!["true"]);
+            }
+            target => panic!("Unexpected target {:?}", target),
+        }
+    }
 }
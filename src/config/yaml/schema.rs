@@ -1,6 +1,6 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 /// Schema of the build flow configuration file `zinoma.yml`.
 ///
@@ -41,7 +41,7 @@ use std::collections::HashMap;
 ///     input:
 ///       - download_dependencies.output
 ///       - paths: [package.json, src]
-///     service: exec npm run start
+///     service: [npm, run, start]
 ///
 ///   build:
 ///     dependencies: [check]
@@ -162,6 +162,168 @@ pub struct Project {
     /// In this example, the target `test_all` depend from targets defined in different projects.
     #[serde(default)]
     pub imports: HashMap<String, String>,
+
+    /// Import all the Žinoma projects found in directories matching glob patterns.
+    ///
+    /// `workspaces` should be a list of glob patterns, relative to the project directory.
+    /// Every matching directory containing a `zinoma.yml` file is imported under its declared `name`,
+    /// just like the projects listed in [`imports`].
+    ///
+    /// [`imports`]: struct.Project.html#structfield.imports
+    ///
+    /// Two projects cannot have the same name.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// workspaces:
+    ///   - packages/*
+    ///   - services/**
+    ///
+    /// targets:
+    ///   test_all:
+    ///     dependencies: [api::test, webapp::test]
+    /// ```
+    #[serde(default)]
+    pub workspaces: Vec<String>,
+
+    /// Variables of the project.
+    ///
+    /// `vars` should be an object, the keys being the variable names and the values their respective values.
+    /// A variable name should contain only alphanumeric characters or `_`.
+    ///
+    /// Variables can be referenced as `${variable_name}` in the scripts, dependencies, and input and output resources of the project targets.
+    ///
+    /// The following variables are also available:
+    ///
+    /// - `${env:NAME}`: value of the environment variable `NAME`;
+    /// - `${project_dir}`: absolute path of the project directory;
+    /// - `${target_name}`: name of the current target.
+    ///
+    /// Referencing an unknown variable or an undefined environment variable is an error.
+    /// A literal `${` should be escaped as `$${`, e.g. for a shell expansion such as `$${HOME}`.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// vars:
+    ///   api_dir: packages/api
+    ///   image: my-registry/api
+    ///
+    /// targets:
+    ///   build_image:
+    ///     input:
+    ///       - paths: [${api_dir}/src, ${api_dir}/Dockerfile]
+    ///     output:
+    ///       - cmd_stdout: docker image ls ${image}:latest --format "{{.ID}}"
+    ///     build: docker build -t ${image}:latest ${api_dir}
+    ///
+    ///   print_home:
+    ///     build: echo "$${HOME}"
+    /// ```
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+
+    /// Templates of targets.
+    ///
+    /// `templates` should be an object, the keys being the template names and the values their respective [`definitions`].
+    ///
+    /// [`definitions`]: struct.Template.html
+    ///
+    /// Targets can inherit from a template with the [`extends`] field.
+    /// Templates of imported projects can be referenced by specifying their fully qualified name: `imported_project_name::template_name`.
+    ///
+    /// [`extends`]: enum.Target.html#variant.Extended.field.extends
+    ///
+    /// __Example__
+    ///
+    /// `packages/rust/zinoma.yml`:
+    ///
+    /// ```yaml
+    /// name: rust
+    ///
+    /// templates:
+    ///   cargo:
+    ///     input:
+    ///       - paths: [Cargo.toml, Cargo.lock, src]
+    /// ```
+    ///
+    /// `./zinoma.yml`:
+    ///
+    /// ```yaml
+    /// imports:
+    ///   rust: packages/rust
+    ///
+    /// targets:
+    ///   test:
+    ///     extends: rust::cargo
+    ///     build: cargo test
+    /// ```
+    #[serde(default)]
+    pub templates: HashMap<String, Template>,
+
+    /// Default [`Shell`] running the scripts of the project targets.
+    ///
+    /// [`Shell`]: enum.Shell.html
+    ///
+    /// Targets can override it with their own `shell` field.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// shell: bash -euo pipefail
+    ///
+    /// targets:
+    ///   check_versions:
+    ///     build: diff <(node --version) .nvmrc
+    ///
+    ///   print_version:
+    ///     shell: python3
+    ///     build: |
+    ///       import json
+    ///       print(json.load(open("package.json"))["version"])
+    /// ```
+    #[serde(default)]
+    pub shell: Option<Shell>,
+
+    /// Dotenv files loaded in the environment of the build and service scripts of the project targets.
+    ///
+    /// It should be an array of paths, relative to the project directory.
+    /// The files are loaded in order, before the env files of the target.
+    /// Their variables override the variables inherited from the environment of `zinoma`.
+    /// Values can reference other variables with `${NAME}`. Missing files are ignored.
+    ///
+    /// The env files are watched like the input resources of the targets using them.
+    /// A change in an env file also invalidates the incremental build of the targets declaring `input`,
+    /// while targets without `input` always run.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// env_file: [.env, .env.local]
+    ///
+    /// targets:
+    ///   start:
+    ///     env_file: [.env.dev]
+    ///     service: [npm, run, start]
+    /// ```
+    #[serde(default)]
+    pub env_file: Vec<String>,
+
+    /// [`HashAlgorithm`] computing the checksums of the files used as resources by the project targets.
+    ///
+    /// [`HashAlgorithm`]: enum.HashAlgorithm.html
+    ///
+    /// Changing the algorithm invalidates the checksums computed previously,
+    /// hence the targets of the project run again on their next execution.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// hash_algorithm: sha256
+    /// ```
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
 }
 
 /// A target is a command or a set of commands to run as part of your build flow.
@@ -170,7 +332,7 @@ pub struct Project {
 /// To force targets to run sequentially, you can define [`dependencies`] on other targets.
 ///
 /// [`dependencies`]: struct.Dependencies.html
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, untagged)]
 pub enum Target {
     /// A build target represents a shell script to run as part of your build flow.
@@ -184,9 +346,80 @@ pub enum Target {
         #[serde(default)]
         dependencies: Dependencies,
 
-        /// The shell script to run in order to build this target.
+        /// Targets which should run before this target, if they are also part of the build flow.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// As opposed to dependencies, these targets are not run unless they are requested otherwise,
+        /// and their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        after: Dependencies,
+
+        /// Dependencies of the target which are allowed to fail.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// These targets are run before this target, but their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        optional_dependencies: Dependencies,
+
+        /// Matrix of values expanding this target into several targets.
+        #[serde(default)]
+        matrix: Matrix,
+
+        /// Tags of the target.
+        #[serde(default)]
+        tags: Tags,
+
+        /// [`Condition`] to run the target.
+        ///
+        /// [`Condition`]: struct.When.html
+        #[serde(default)]
+        when: Option<When>,
+
+        /// [`Shell`] running the script of the target.
+        ///
+        /// It overrides the shell of the project.
+        ///
+        /// [`Shell`]: enum.Shell.html
+        #[serde(default)]
+        shell: Option<Shell>,
+
+        /// Working directory of the script, relative to the project directory.
+        ///
+        /// The directory must exist inside the project directory, and it is checked for all the targets, selected or not.
+        /// By default, scripts run in the project directory.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   frontend_test:
+        ///     workdir: frontend
+        ///     input:
+        ///       - paths: [frontend/src]
+        ///     build: npm test
+        /// ```
+        #[serde(default)]
+        workdir: Option<String>,
+
+        /// Dotenv files loaded in the environment of the script, relative to the project directory.
+        ///
+        /// It should be an array of strings. The files are loaded after the env files of the project.
+        /// See [`env_file`] for more details.
+        ///
+        /// [`env_file`]: struct.Project.html#structfield.env_file
+        #[serde(default)]
+        env_file: Vec<String>,
+
+        /// The [`Script`] to run in order to build this target.
+        ///
+        /// [`Script`]: enum.Script.html
         ///
         /// It should be a string. This string can be multi-line, in case of scripts with multiple commands.
+        /// It can also be an array of strings, to run a program without any shell.
         ///
         /// __Example__
         ///
@@ -201,7 +434,109 @@ pub enum Target {
         /// ```
         ///
         /// In this example, running `zinoma create_file_deep` will execute the commands `mkdir -p deep/dir` and `touch deep/dir/my_file` sequentially.
-        build: String,
+        build: Script,
+
+        /// Finalizers of the target.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// These targets are executed once this target and the targets depending on it are done,
+        /// even if they failed or if Žinoma was interrupted.
+        /// Pressing Ctrl-C a second time stops the finalizers which are still running.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   compose_up:
+        ///     build: docker compose up -d
+        ///     finally: [compose_down]
+        ///
+        ///   compose_down:
+        ///     build: docker compose down
+        ///
+        ///   integration_tests:
+        ///     dependencies: [compose_up]
+        ///     build: cargo test --test integ
+        /// ```
+        ///
+        /// In this example, `zinoma integration_tests` will stop the containers once the tests are done, whether they pass or not.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        finally: Dependencies,
+
+        /// Whether the build should be skipped when all the output paths of the target exist.
+        ///
+        /// It is meant for targets without input, such as one-time setup or generation steps,
+        /// which the incremental build would otherwise run every time.
+        /// Such targets can not declare input resources, and should declare at least one output path.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   download_toolchain:
+        ///     build: ./scripts/download-toolchain.sh
+        ///     run_if_missing: true
+        ///     output:
+        ///       - paths: [toolchain]
+        /// ```
+        ///
+        /// In this example, the toolchain is downloaded once, and again if the `toolchain` directory is deleted.
+        #[serde(default)]
+        run_if_missing: bool,
+
+        /// Whether the build script runs in a sandbox, which only exposes the declared resources of the target.
+        ///
+        /// This option is only supported on Linux, and requires [bubblewrap](https://github.com/containers/bubblewrap).
+        ///
+        /// In the sandbox, the input paths are read-only, and the output paths are writable.
+        /// Output paths which do not exist yet are created empty before the build,
+        /// as a directory if the resource has `extensions` or if the path ends with `/`, as a file otherwise.
+        /// The ones the build leaves untouched are removed afterwards.
+        /// As they are mounted in the sandbox, the output paths themselves can not be deleted or renamed by the build.
+        /// The system directories (such as `/usr` and `/etc`) are read-only, and `/tmp` is empty.
+        /// The other files of the project, and the home directory, are not visible.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   build:
+        ///     input:
+        ///       - paths: [src, package.json, node_modules]
+        ///     output:
+        ///       - paths: [dist]
+        ///     build: npm run build
+        ///     sandbox: true
+        /// ```
+        ///
+        /// In this example, the build fails if it reads a file which is not in its input,
+        /// which guarantees that the incremental build does not skip it wrongly.
+        #[serde(default)]
+        sandbox: bool,
+
+        /// Whether the output paths of the target are cleaned before each build.
+        ///
+        /// They are cleaned as with the `--clean` flag, but only when the build is not skipped,
+        /// so that the outputs only contain the files produced by the latest build.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   compile_ts:
+        ///     input:
+        ///       - paths: [src]
+        ///     output:
+        ///       - paths: [dist]
+        ///     build: tsc --outDir dist
+        ///     clean_before_build: true
+        /// ```
+        ///
+        /// In this example, deleting a file of `src` also deletes the compiled file from `dist` upon the next build.
+        #[serde(default)]
+        clean_before_build: bool,
 
         /// Input resources of the target.
         #[serde(default)]
@@ -220,9 +555,79 @@ pub enum Target {
         #[serde(default)]
         dependencies: Dependencies,
 
-        /// Shell script starting a long-lasting service.
+        /// Targets which should run before this target, if they are also part of the build flow.
         ///
-        /// It should be a string.
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// As opposed to dependencies, these targets are not run unless they are requested otherwise,
+        /// and their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        after: Dependencies,
+
+        /// Dependencies of the target which are allowed to fail.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// These targets are run before this target, but their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        optional_dependencies: Dependencies,
+
+        /// Matrix of values expanding this target into several targets.
+        #[serde(default)]
+        matrix: Matrix,
+
+        /// Tags of the target.
+        #[serde(default)]
+        tags: Tags,
+
+        /// [`Condition`] to run the target.
+        ///
+        /// [`Condition`]: struct.When.html
+        #[serde(default)]
+        when: Option<When>,
+
+        /// [`Shell`] running the script of the target.
+        ///
+        /// It overrides the shell of the project.
+        ///
+        /// [`Shell`]: enum.Shell.html
+        #[serde(default)]
+        shell: Option<Shell>,
+
+        /// Working directory of the script, relative to the project directory.
+        ///
+        /// The directory must exist inside the project directory, and it is checked for all the targets, selected or not.
+        /// By default, scripts run in the project directory.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   frontend_test:
+        ///     workdir: frontend
+        ///     input:
+        ///       - paths: [frontend/src]
+        ///     build: npm test
+        /// ```
+        #[serde(default)]
+        workdir: Option<String>,
+
+        /// Dotenv files loaded in the environment of the script, relative to the project directory.
+        ///
+        /// It should be an array of strings. The files are loaded after the env files of the project.
+        /// See [`env_file`] for more details.
+        ///
+        /// [`env_file`]: struct.Project.html#structfield.env_file
+        #[serde(default)]
+        env_file: Vec<String>,
+
+        /// [`Script`] starting a long-lasting service.
+        ///
+        /// [`Script`]: enum.Script.html
+        ///
+        /// It should be a string, or an array of strings to run a program without any shell.
         ///
         /// If `zinoma` has no service target to run, it will automatically exit after all build targets ran to completion.
         /// On the contrary, if at least one service target is specified in the command line,
@@ -242,7 +647,7 @@ pub enum Target {
         ///
         /// In this example, `zinoma npm_server --watch` will run `npm start`,
         /// and will restart this process every time `package.json` or `index.js` are updated.
-        service: String,
+        service: Script,
 
         /// Input resources of the target.
         #[serde(default)]
@@ -270,10 +675,256 @@ pub enum Target {
     Aggregate {
         /// Dependencies of the target.
         dependencies: Dependencies,
+
+        /// Targets which should run before this target, if they are also part of the build flow.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// As opposed to dependencies, these targets are not run unless they are requested otherwise,
+        /// and their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        after: Dependencies,
+
+        /// Dependencies of the target which are allowed to fail.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// These targets are run before this target, but their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        optional_dependencies: Dependencies,
+
+        /// Matrix of values expanding this target into several targets.
+        #[serde(default)]
+        matrix: Matrix,
+
+        /// Tags of the target.
+        #[serde(default)]
+        tags: Tags,
+    },
+
+    /// Target inheriting its definition from a [`template`].
+    ///
+    /// [`template`]: struct.Template.html
+    ///
+    /// The `dependencies`, `input` and `output` of the target are appended to the ones of the template.
+    /// The `build` or `service` script of the target, if any, overrides the one of the template.
+    ///
+    /// The resulting target is a build target if it has a `build` script, a service target if it has a `service` script,
+    /// or an aggregate target otherwise.
+    ///
+    /// __Example__
+    ///
+    /// ```yaml
+    /// templates:
+    ///   cargo:
+    ///     input:
+    ///       - paths: [Cargo.toml, Cargo.lock, src]
+    ///
+    /// targets:
+    ///   test:
+    ///     extends: cargo
+    ///     input:
+    ///       - paths: [tests]
+    ///     build: cargo test
+    ///
+    ///   build:
+    ///     extends: cargo
+    ///     output:
+    ///       - paths: [target/release/my_app]
+    ///     build: cargo build --release
+    /// ```
+    Extended {
+        /// Name of the template to inherit from.
+        ///
+        /// It should be a string with the format `<project_name>::<template_name>`.
+        /// If the template is defined in the same project, the project name can be skipped.
+        extends: String,
+
+        /// Dependencies of the target.
+        #[serde(default)]
+        dependencies: Dependencies,
+
+        /// Targets which should run before this target, if they are also part of the build flow.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// As opposed to dependencies, these targets are not run unless they are requested otherwise,
+        /// and their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        after: Dependencies,
+
+        /// Dependencies of the target which are allowed to fail.
+        ///
+        /// It should be an array of strings, with the same format as [`dependencies`].
+        /// These targets are run before this target, but their failure does not prevent this target from running.
+        ///
+        /// [`dependencies`]: struct.Dependencies.html
+        #[serde(default)]
+        optional_dependencies: Dependencies,
+
+        /// Matrix of values expanding this target into several targets.
+        #[serde(default)]
+        matrix: Matrix,
+
+        /// Tags of the target.
+        #[serde(default)]
+        tags: Tags,
+
+        /// [`Condition`] to run the target.
+        ///
+        /// [`Condition`]: struct.When.html
+        #[serde(default)]
+        when: Option<When>,
+
+        /// [`Shell`] running the script of the target.
+        ///
+        /// It overrides the shell of the project.
+        ///
+        /// [`Shell`]: enum.Shell.html
+        #[serde(default)]
+        shell: Option<Shell>,
+
+        /// Working directory of the script, relative to the project directory.
+        ///
+        /// The directory must exist inside the project directory, and it is checked for all the targets, selected or not.
+        /// By default, scripts run in the project directory.
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   frontend_test:
+        ///     workdir: frontend
+        ///     input:
+        ///       - paths: [frontend/src]
+        ///     build: npm test
+        /// ```
+        #[serde(default)]
+        workdir: Option<String>,
+
+        /// Dotenv files loaded in the environment of the script, relative to the project directory.
+        #[serde(default)]
+        env_file: Vec<String>,
+
+        /// The script to run in order to build this target.
+        #[serde(default)]
+        build: Option<Script>,
+
+        /// Script starting a long-lasting service.
+        #[serde(default)]
+        service: Option<Script>,
+
+        /// Finalizers of the target, added to the finalizers of the template.
+        #[serde(default)]
+        finally: Dependencies,
+
+        /// Whether the build should be skipped when all the output paths of the target exist.
+        ///
+        /// It overrides the value of the template.
+        #[serde(default)]
+        run_if_missing: Option<bool>,
+
+        /// Whether the build script runs in a sandbox.
+        ///
+        /// It overrides the value of the template.
+        #[serde(default)]
+        sandbox: Option<bool>,
+
+        /// Whether the output paths of the target are cleaned before each build.
+        ///
+        /// It overrides the value of the template.
+        #[serde(default)]
+        clean_before_build: Option<bool>,
+
+        /// Input resources of the target.
+        #[serde(default)]
+        input: InputResources,
+
+        /// Output resources of the target.
+        #[serde(default)]
+        output: OutputResources,
     },
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Reusable parts of target definitions.
+///
+/// Targets can inherit from a template by specifying its name in their [`extends`] field.
+///
+/// [`extends`]: enum.Target.html#variant.Extended.field.extends
+///
+/// Dependencies and resource paths of the template are relative to the project of the extending target.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    /// Dependencies of the targets extending this template.
+    #[serde(default)]
+    pub dependencies: Dependencies,
+
+    /// Targets which should run before the targets extending this template, if they are also part of the build flow.
+    #[serde(default)]
+    pub after: Dependencies,
+
+    /// Dependencies of the targets extending this template which are allowed to fail.
+    #[serde(default)]
+    pub optional_dependencies: Dependencies,
+
+    /// Tags of the targets extending this template.
+    #[serde(default)]
+    pub tags: Tags,
+
+    /// Condition to run the targets extending this template.
+    #[serde(default)]
+    pub when: Option<When>,
+
+    /// Shell running the script of the targets extending this template.
+    #[serde(default)]
+    pub shell: Option<Shell>,
+
+    /// Working directory of the script of the targets extending this template.
+    #[serde(default)]
+    pub workdir: Option<String>,
+
+    /// Dotenv files loaded in the environment of the targets extending this template.
+    #[serde(default)]
+    pub env_file: Vec<String>,
+
+    /// The script to run in order to build the targets extending this template.
+    #[serde(default)]
+    pub build: Option<Script>,
+
+    /// Script starting a long-lasting service for the targets extending this template.
+    #[serde(default)]
+    pub service: Option<Script>,
+
+    /// Finalizers of the targets extending this template.
+    #[serde(default)]
+    pub finally: Dependencies,
+
+    /// Whether the build of the targets extending this template should be skipped when all their output paths exist.
+    #[serde(default)]
+    pub run_if_missing: bool,
+
+    /// Whether the build script of the targets extending this template runs in a sandbox.
+    #[serde(default)]
+    pub sandbox: bool,
+
+    /// Whether the output paths of the targets extending this template are cleaned before each build.
+    #[serde(default)]
+    pub clean_before_build: bool,
+
+    /// Input resources of the targets extending this template.
+    #[serde(default)]
+    pub input: InputResources,
+
+    /// Output resources of the targets extending this template.
+    #[serde(default)]
+    pub output: OutputResources,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, untagged)]
 pub enum InputResource {
     /// Output resources of another target.
@@ -347,7 +998,7 @@ pub enum InputResource {
         ///     input:
         ///       - paths: [src, tests]
         ///         extensions: [rs]
-        ///     build: exec cargo fmt --all -- --check
+        ///     build: [cargo, fmt, --all, --, --check]
         extensions: Option<Vec<String>>,
     },
     CmdStdout {
@@ -368,10 +1019,15 @@ pub enum InputResource {
         ///     build: docker build -t webapp .
         /// ```
         cmd_stdout: String,
+        /// Working directory of the script, relative to the project directory.
+        ///
+        /// The directory must exist inside the project directory. By default, the script runs in the project directory.
+        #[serde(default)]
+        workdir: Option<String>,
     },
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields, untagged)]
 pub enum OutputResource {
     Files {
@@ -423,6 +1079,31 @@ pub enum OutputResource {
         ///       cd protos
         ///       docker run -v `pwd`:/defs namely/protoc-all -d . -o . -l go
         extensions: Option<Vec<String>>,
+        /// Whether the paths should exist once the build script completes.
+        ///
+        /// By default, the build of the target fails if any of the paths is missing after its script completes.
+        /// Set it to `false` for outputs which the script may not produce.
+        #[serde(default = "default_output_required")]
+        required: bool,
+        /// Whether the paths should not be empty once the build script completes.
+        ///
+        /// If `true`, the build of the target fails if a file is empty,
+        /// or if a directory contains no file (matching the `extensions`, if any).
+        ///
+        /// __Example__
+        ///
+        /// ```yaml
+        /// targets:
+        ///   package_lambda:
+        ///     input:
+        ///       - paths: [lambda]
+        ///     output:
+        ///       - paths: [lambda.zip]
+        ///         non_empty: true
+        ///     build: zip -r lambda.zip lambda
+        /// ```
+        #[serde(default)]
+        non_empty: bool,
     },
     CmdStdout {
         /// Shell script whose output identifies the state of a resource.
@@ -442,9 +1123,18 @@ pub enum OutputResource {
         ///     build: docker build -t webapp .
         /// ```
         cmd_stdout: String,
+        /// Working directory of the script, relative to the project directory.
+        ///
+        /// The directory must exist inside the project directory. By default, the script runs in the project directory.
+        #[serde(default)]
+        workdir: Option<String>,
     },
 }
 
+fn default_output_required() -> bool {
+    true
+}
+
 /// List of [`targets`] that must complete successfully before this target can be built.
 ///
 /// [`targets`]: enum.Target.html
@@ -470,7 +1160,24 @@ pub enum OutputResource {
 /// `zinoma target2` will run sequentially `target1` and `target2`.
 ///
 /// `zinoma target3` will run sequentially `target1`, `target2` and `target3`.
-#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+///
+/// Dependencies can be loosened with `after` (ordering only) and `optional_dependencies` (allowed to fail):
+///
+/// ```yaml
+/// targets:
+///   fmt:
+///     build: cargo fmt
+///   lint:
+///     after: [fmt]
+///     build: cargo clippy
+///   coverage:
+///     optional_dependencies: [test]
+///     build: ./upload_coverage.sh
+/// ```
+///
+/// In this example, `zinoma lint` only runs `lint`, while `zinoma fmt lint` runs `fmt` before `lint`.
+/// `zinoma coverage` runs `test` then `coverage`, even if `test` fails.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct Dependencies(#[serde(default)] pub Vec<String>);
 
@@ -497,7 +1204,7 @@ pub struct Dependencies(#[serde(default)] pub Vec<String>);
 ///
 /// In this example, running `zinoma npm_install` once will execute `npm install`.
 /// Subsequent runs of `zinoma npm_install` will return immediately — until the content of `package.json` or `package-lock.json` is modified.
-#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct InputResources(#[serde(default)] pub Vec<InputResource>);
 
@@ -527,6 +1234,194 @@ pub struct InputResources(#[serde(default)] pub Vec<InputResource>);
 /// In this example, running `zinoma npm_install` will return immediately in case `package.json`, `package-lock.json` and `node_modules` were not modified since the last completion of the target.
 ///
 /// Running `zinoma --clean npm_install` will start by deleting `node_modules`, then will run `npm install`.
-#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct OutputResources(#[serde(default)] pub Vec<OutputResource>);
+
+/// Tags of a target.
+///
+/// It should be an array of strings.
+///
+/// Tags can be used to select targets from all projects on the command line,
+/// with the `--tag` and `--exclude-tag` options or with `@<tag>` in place of a target name.
+/// The dependencies of the selected targets are run as well.
+///
+/// __Example__
+///
+/// ```yaml
+/// targets:
+///   lint:
+///     tags: [ci]
+///     build: cargo clippy
+///   test:
+///     tags: [ci]
+///     build: cargo test
+///   e2e:
+///     tags: [ci, slow]
+///     build: npm run e2e
+/// ```
+///
+/// In this example, `zinoma --tag ci --exclude-tag slow` runs the targets `lint` and `test`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Tags(#[serde(default)] pub Vec<String>);
+
+/// Script of a build or service target.
+///
+/// It can either be:
+///
+/// - a string, run by the [`Shell`] of the target;
+/// - an array of strings, the program to run followed by its arguments.
+///   The program is spawned directly, without any shell, and the [`Shell`] of the target is ignored.
+///   This avoids quoting issues and the startup cost of the shell.
+///   This is also the best option for services, as the signals are delivered to the program itself.
+///
+/// [`Shell`]: enum.Shell.html
+///
+/// __Example__
+///
+/// ```yaml
+/// targets:
+///   test:
+///     build: [cargo, test, --all]
+///
+///   start:
+///     service: [npm, run, start]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Script {
+    /// Script run by the shell.
+    Shell(String),
+    /// Program followed by its arguments.
+    Exec(Vec<String>),
+}
+
+/// Interpreter running the scripts of a target.
+///
+/// By default, scripts are run with `/bin/sh -ce` on Unix, and with `cmd.exe /C` on Windows.
+///
+/// It can either be:
+///
+/// - a command line, such as `bash -euo pipefail`, `zsh`, `python3` or `node`.
+///   The script is passed with the flag the interpreter expects: `/C` for `cmd`, `-Command` for `powershell` and `pwsh`,
+///   `-e` for `node`, `perl` and `ruby`, and `-c` otherwise;
+/// - an explicit list of arguments, such as `[deno, eval]`. The script is passed as the last argument.
+///
+/// __Example__
+///
+/// ```yaml
+/// targets:
+///   greet:
+///     shell: node
+///     build: console.log("Hello from Node.js")
+///
+///   count_lines:
+///     shell: [python3, -c]
+///     build: print(sum(1 for _ in open("README.md")))
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Shell {
+    /// Command line of the interpreter.
+    CommandLine(String),
+    /// Program and arguments of the interpreter, followed by the script.
+    Arguments(Vec<String>),
+}
+
+/// Algorithm computing the checksums of files.
+///
+/// It can either be:
+///
+/// - `blake3` (default): a fast cryptographic hash function;
+/// - `sha256`: slower, but widely supported by other tools, such as `sha256sum`;
+/// - `xxh3`: the fastest, but not collision-resistant. It should not be used for checksums shared across machines.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+    Xxh3,
+}
+
+/// Condition to run a target.
+///
+/// All the specified conditions should be met for the target to run.
+/// Otherwise, the target is reported as skipped, and its dependents run as if it had completed successfully.
+///
+/// __Example__
+///
+/// ```yaml
+/// targets:
+///   upload_coverage:
+///     when:
+///       env: [CI, COVERAGE=true]
+///       os: [linux, macos]
+///       cmd: command -v codecov
+///     build: codecov
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct When {
+    /// Environment variables to check.
+    ///
+    /// Each item should either be `NAME`, requiring the environment variable `NAME` to be set to a non-empty value,
+    /// or `NAME=value`, requiring the environment variable `NAME` to be set to `value`.
+    #[serde(default)]
+    pub env: Vec<String>,
+
+    /// Operating systems on which the target should run.
+    ///
+    /// The possible values include `linux`, `macos` and `windows`.
+    #[serde(default)]
+    pub os: Vec<String>,
+
+    /// Shell script evaluated in the project directory, with the shell of the target.
+    ///
+    /// The condition is met if the script exits successfully.
+    #[serde(default)]
+    pub cmd: Option<String>,
+}
+
+/// Matrix of values expanding a target into several concrete targets.
+///
+/// It should be an object, the keys being the matrix variable names and the values arrays of possible values.
+///
+/// One target is generated for each combination of values.
+/// The generated targets are named after the original target and their values, e.g. `test[node=14]`.
+/// These names should be quoted in most shells.
+/// Values are used as written in the file (e.g. `3.10` remains `3.10`),
+/// and may only contain letters, digits, underscores, hyphens and dots.
+///
+/// Within the target definition, `${matrix.<variable_name>}` is replaced with the value of the variable.
+/// This applies to the scripts, dependencies, and input and output resources of the target.
+///
+/// Additionally, the original target name becomes an aggregate of all the generated targets.
+///
+/// __Example__
+///
+/// ```yaml
+/// targets:
+///   test:
+///     matrix:
+///       node: [14, 16]
+///     input:
+///       - paths: [package.json, src, test]
+///     build: npx -p node@${matrix.node} -- npm test
+/// ```
+///
+/// In this example, the targets `test[node=14]` and `test[node=16]` are generated.
+/// `zinoma test` runs both of them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix(#[serde(default)] pub BTreeMap<String, Vec<MatrixValue>>);
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MatrixValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
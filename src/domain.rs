@@ -1,5 +1,6 @@
 use anyhow::{anyhow, Result};
 use async_std::path::PathBuf;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use std::fmt;
 
@@ -8,6 +9,9 @@ pub struct TargetMetadata {
     pub id: TargetId,
     pub project_dir: PathBuf,
     pub dependencies: Vec<TargetId>,
+    pub optional_dependencies: Vec<TargetId>,
+    /// Targets to run before this one, if they are part of the build flow.
+    pub after: Vec<TargetId>,
 }
 
 impl fmt::Display for TargetMetadata {
@@ -19,9 +23,38 @@ impl fmt::Display for TargetMetadata {
 #[derive(Debug)]
 pub struct BuildTarget {
     pub metadata: TargetMetadata,
-    pub build_script: String,
+    pub condition: Condition,
+    pub build_script: Script,
+    pub shell: Option<Shell>,
+    pub workdir: PathBuf,
+    /// Dotenv files loaded in the environment of the script.
+    pub env_files: Vec<PathBuf>,
+    /// Algorithm computing the checksums of the input and output files.
+    pub hash_algorithm: HashAlgorithm,
+    pub finalizers: Vec<TargetId>,
+    /// Whether the build is skipped when all the output paths exist, regardless of the incremental state.
+    pub run_if_missing: bool,
+    /// Whether the build script only sees the declared input and output paths.
+    pub sandbox: bool,
+    /// Whether the output paths are cleaned before each build which is not skipped.
+    pub clean_before_build: bool,
     pub input: Resources,
     pub output: Resources,
+    /// Output paths which should exist once the build script completes.
+    pub required_outputs: Vec<RequiredOutput>,
+}
+
+impl BuildTarget {
+    /// Input resources whose state is recorded upon a successful build: the declared input and the env files.
+    ///
+    /// Targets without declared input always run, hence their env files are not tracked either.
+    pub fn tracked_input(&self) -> Resources {
+        if self.input.is_empty() {
+            Resources::new()
+        } else {
+            with_env_files(&self.input, &self.env_files)
+        }
+    }
 }
 
 impl fmt::Display for BuildTarget {
@@ -33,7 +66,12 @@ impl fmt::Display for BuildTarget {
 #[derive(Debug)]
 pub struct ServiceTarget {
     pub metadata: TargetMetadata,
-    pub run_script: String,
+    pub condition: Condition,
+    pub run_script: Script,
+    pub shell: Option<Shell>,
+    pub workdir: PathBuf,
+    /// Dotenv files loaded in the environment of the script.
+    pub env_files: Vec<PathBuf>,
     pub input: Resources,
 }
 
@@ -43,6 +81,56 @@ impl fmt::Display for ServiceTarget {
     }
 }
 
+/// Condition to run a target. An empty condition is always met.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Condition {
+    pub env: Vec<EnvCondition>,
+    pub os: Vec<String>,
+    pub cmd: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EnvCondition {
+    NonEmpty(String),
+    Equals(String, String),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Script {
+    /// Script run by the shell of the target.
+    Shell(String),
+    /// Program and arguments, run without any shell.
+    Exec(Vec<String>),
+}
+
+/// Interpreter of the scripts of a target.
+///
+/// The script is passed as an additional argument, after `args`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Shell {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+    Xxh3,
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        };
+        write!(fmt, "{}", name)
+    }
+}
+
 #[derive(Debug)]
 pub struct AggregateTarget {
     pub metadata: TargetMetadata,
@@ -68,17 +156,25 @@ impl Target {
         &self.metadata().id
     }
 
-    pub fn dependencies(&self) -> &Vec<TargetId> {
-        &self.metadata().dependencies
+    /// Lists the targets which should run whenever this target is requested.
+    pub fn requested_dependencies(&self) -> impl Iterator<Item = &TargetId> {
+        let metadata = self.metadata();
+        metadata
+            .dependencies
+            .iter()
+            .chain(&metadata.optional_dependencies)
     }
 
-    pub fn extend_dependencies(&mut self, additional_dependencies: &[TargetId]) {
-        let metadata = match self {
+    pub fn metadata_mut(&mut self) -> &mut TargetMetadata {
+        match self {
             Target::Build(target) => &mut target.metadata,
             Target::Service(target) => &mut target.metadata,
             Target::Aggregate(target) => &mut target.metadata,
-        };
-        metadata
+        }
+    }
+
+    pub fn extend_dependencies(&mut self, additional_dependencies: &[TargetId]) {
+        self.metadata_mut()
             .dependencies
             .extend_from_slice(additional_dependencies);
     }
@@ -91,6 +187,16 @@ impl Target {
         }
     }
 
+    /// Input resources whose changes invalidate the target: the declared input and the env files.
+    pub fn watched_input(&self) -> Option<Resources> {
+        let env_files = match self {
+            Target::Build(target) => &target.env_files,
+            Target::Service(target) => &target.env_files,
+            Target::Aggregate(_) => return None,
+        };
+        self.input().map(|input| with_env_files(input, env_files))
+    }
+
     pub fn output(&self) -> Option<&Resources> {
         match self {
             Target::Build(target) => Some(&target.output),
@@ -111,6 +217,18 @@ impl Target {
     }
 }
 
+/// Env files affect the execution of the scripts, hence they are treated as input resources.
+fn with_env_files(input: &Resources, env_files: &[PathBuf]) -> Resources {
+    let mut resources = input.clone();
+    if !env_files.is_empty() {
+        resources.files.push(FilesResource {
+            paths: env_files.to_vec(),
+            extensions: None,
+        });
+    }
+    resources
+}
+
 impl fmt::Display for Target {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(fmt, "{}", self.id())
@@ -153,6 +271,102 @@ impl TargetId {
     }
 }
 
+/// Pattern selecting targets, such as `api::test`, `*::test`, `api::*` or `...::lint`.
+///
+/// `*` matches any sequence of characters within a project or target name.
+/// `...` matches any project, including the root project when it has no name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetPattern {
+    project_name: ProjectNamePattern,
+    target_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ProjectNamePattern {
+    Any,
+    Matching(Option<String>),
+}
+
+impl TargetPattern {
+    pub fn try_parse(pattern: &str, current_project: &Option<String>) -> Result<Self> {
+        let parts = pattern.split("::").collect::<Vec<_>>();
+        let (project_name, target_name) = match parts[..] {
+            ["...", target_name] => (ProjectNamePattern::Any, target_name),
+            [project_name, target_name] => (
+                ProjectNamePattern::Matching(Some(project_name.to_owned())),
+                target_name,
+            ),
+            [target_name] => (
+                ProjectNamePattern::Matching(current_project.clone()),
+                target_name,
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "Invalid target pattern: {} (expected a maximum of one '::' delimiter)",
+                    pattern
+                ))
+            }
+        };
+
+        Ok(Self {
+            project_name,
+            target_name: target_name.to_owned(),
+        })
+    }
+
+    /// Returns the id of the single target selected by this pattern, if it has no wildcard.
+    pub fn as_target_id(&self) -> Option<TargetId> {
+        match &self.project_name {
+            ProjectNamePattern::Matching(project_name) if !self.to_string().contains('*') => {
+                Some(TargetId {
+                    project_name: project_name.clone(),
+                    target_name: self.target_name.clone(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, target_id: &TargetId) -> bool {
+        let project_name_matches = match (&self.project_name, &target_id.project_name) {
+            (ProjectNamePattern::Any, _) => true,
+            (ProjectNamePattern::Matching(Some(pattern)), Some(project_name)) => {
+                matches_wildcard(pattern, project_name)
+            }
+            (ProjectNamePattern::Matching(None), None) => true,
+            _ => false,
+        };
+
+        project_name_matches && matches_wildcard(&self.target_name, &target_id.target_name)
+    }
+}
+
+fn matches_wildcard(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => value.strip_prefix(prefix).is_some_and(|value| {
+            value
+                .char_indices()
+                .map(|(index, _)| index)
+                .chain(std::iter::once(value.len()))
+                .any(|index| matches_wildcard(rest, &value[index..]))
+        }),
+    }
+}
+
+impl fmt::Display for TargetPattern {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.project_name {
+            ProjectNamePattern::Any => fmt.write_str("...::")?,
+            ProjectNamePattern::Matching(Some(project_name)) => {
+                fmt.write_fmt(format_args!("{}::", project_name))?
+            }
+            ProjectNamePattern::Matching(None) => {}
+        }
+        fmt.write_str(&self.target_name)
+    }
+}
+
 impl fmt::Display for TargetId {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(project_name) = &self.project_name {
@@ -177,6 +391,15 @@ pub fn matches_extensions(file: &std::path::Path, extensions: &FileExtensions) -
     })
 }
 
+/// Output path which should exist once the build script of its target completes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RequiredOutput {
+    pub path: PathBuf,
+    pub extensions: FileExtensions,
+    /// Whether the file should not be empty, or the directory should contain files.
+    pub non_empty: bool,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct CmdResource {
     pub cmd: String,
@@ -1,7 +1,9 @@
-use crate::domain::BuildTarget;
-use crate::run_script;
+use super::{io_tracer, sandbox, TraceIoOption};
+use crate::domain::{BuildTarget, RequiredOutput};
+use crate::{clean, fs, run_script};
 use anyhow::{anyhow, Context, Result};
 use async_std::channel::Receiver;
+use async_std::path::Path;
 use async_std::prelude::*;
 use futures::FutureExt;
 use std::process::Stdio;
@@ -9,17 +11,68 @@ use std::time::Instant;
 
 pub async fn build_target(
     target: &BuildTarget,
+    trace_io_option: TraceIoOption,
+    build_cancellation_events: Receiver<BuildCancellationMessage>,
+) -> Result<BuildTerminationReport> {
+    if target.clean_before_build {
+        log::debug!("{} - Cleaning output paths", target);
+        clean::clean_output_paths(&target.output).await?;
+    }
+
+    match trace_io_option {
+        TraceIoOption::Enabled => {
+            let trace_file = io_tracer::trace_file_path(target);
+            let result = run_build(target, Some(&trace_file), build_cancellation_events).await;
+            if let Ok(BuildTerminationReport::Completed) = result {
+                if let Err(e) = io_tracer::report_undeclared_accesses(target, &trace_file).await {
+                    log::warn!("{} - Failed to report undeclared files: {:?}", target, e)
+                }
+            }
+            let _ = async_std::fs::remove_file(&trace_file).await;
+            result
+        }
+        TraceIoOption::Disabled => run_build(target, None, build_cancellation_events).await,
+    }
+}
+
+async fn run_build(
+    target: &BuildTarget,
+    trace_file: Option<&Path>,
     mut build_cancellation_events: Receiver<BuildCancellationMessage>,
 ) -> Result<BuildTerminationReport> {
     let target_start = Instant::now();
     log::info!("{} - Building", target);
 
-    let mut command = run_script::build_command(&target.build_script, &target.metadata.project_dir);
+    let mut command_line =
+        run_script::script_command_line(&target.build_script, target.shell.as_ref());
+    let sandbox = if target.sandbox {
+        Some(sandbox::Sandbox::prepare(target)?)
+    } else {
+        None
+    };
+    if let Some(sandbox) = &sandbox {
+        command_line = sandbox.command_line(command_line);
+    }
+    if let Some(trace_file) = trace_file {
+        command_line = io_tracer::traced_command_line(trace_file, command_line);
+    }
+    let is_wrapped = target.sandbox || trace_file.is_some();
+
+    let mut command = run_script::build_command_line_command(&command_line, &target.workdir);
+    run_script::load_env_files(&mut command, &target.env_files)?;
     command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
 
-    let mut build_process = command
-        .spawn()
-        .with_context(|| format!("Failed to spawn build command for {}", target))?;
+    let mut build_process = command.spawn().with_context(|| {
+        if is_wrapped {
+            format!(
+                "Failed to spawn {} for {} (is it installed?)",
+                command_line[0].to_string_lossy(),
+                target
+            )
+        } else {
+            format!("Failed to spawn build command for {}", target)
+        }
+    })?;
 
     futures::select! {
         _ = build_cancellation_events.next().fuse() => {
@@ -30,13 +83,22 @@ pub async fn build_target(
             if let Err(e) = build_process.status().await {
                 log::error!("{} - Failed to await build process: {}", target, e)
             }
+            if let Some(sandbox) = &sandbox {
+                sandbox.remove_unused_placeholders();
+            }
             Ok(BuildTerminationReport::Cancelled)
         },
         result = build_process.status().fuse() => {
+            if let Some(sandbox) = &sandbox {
+                sandbox.remove_unused_placeholders();
+            }
             let exit_status = result?;
             if !exit_status.success() {
                 return Err(anyhow!("Build failed with {}", exit_status));
             }
+            for required_output in &target.required_outputs {
+                verify_output(required_output).await?;
+            }
             let target_build_duration = target_start.elapsed();
             log::info!(
                 "{} - Build success (took: {}ms)",
@@ -48,6 +110,31 @@ pub async fn build_target(
     }
 }
 
+async fn verify_output(required_output: &RequiredOutput) -> Result<()> {
+    let path = &required_output.path;
+    if !path.exists().await {
+        return Err(anyhow!(
+            "Build did not produce its output {}",
+            path.display()
+        ));
+    }
+
+    if required_output.non_empty {
+        let is_empty = if path.is_dir().await {
+            fs::list_files_in_paths(std::slice::from_ref(path), &required_output.extensions)
+                .await
+                .is_empty()
+        } else {
+            async_std::fs::metadata(path).await?.len() == 0
+        };
+        if is_empty {
+            return Err(anyhow!("Build produced an empty output {}", path.display()));
+        }
+    }
+
+    Ok(())
+}
+
 pub enum BuildTerminationReport {
     Completed,
     Cancelled,
@@ -0,0 +1,125 @@
+use crate::domain::{Condition, EnvCondition, Shell};
+use crate::run_script;
+use anyhow::{Context, Result};
+use async_std::path::Path;
+use std::ffi::OsString;
+use std::process::Stdio;
+
+/// Evaluates the condition of a target, running its shell condition command if any.
+///
+/// The condition command runs with the shell of the target, like its script.
+pub async fn is_met(condition: &Condition, shell: Option<&Shell>, dir: &Path) -> Result<bool> {
+    if !condition
+        .env
+        .iter()
+        .all(|env_condition| is_env_condition_met(env_condition, |name| std::env::var_os(name)))
+    {
+        return Ok(false);
+    }
+
+    if !condition.os.is_empty() && !condition.os.iter().any(|os| os == std::env::consts::OS) {
+        return Ok(false);
+    }
+
+    if let Some(cmd) = &condition.cmd {
+        let status = run_script::build_shell_command(shell, cmd, dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .with_context(|| format!("Failed to run condition command {}", cmd))?;
+        return Ok(status.success());
+    }
+
+    Ok(true)
+}
+
+fn is_env_condition_met<F>(env_condition: &EnvCondition, get_var: F) -> bool
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    match env_condition {
+        EnvCondition::NonEmpty(name) => get_var(name).is_some_and(|value| !value.is_empty()),
+        EnvCondition::Equals(name, expected_value) => {
+            get_var(name).is_some_and(|value| value == expected_value.as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_env_condition_met, is_met};
+    use crate::domain::{Condition, EnvCondition, Shell};
+    use async_std::path::Path;
+    use async_std::task;
+    use std::ffi::OsString;
+
+    #[test]
+    fn test_is_met_without_condition() {
+        let condition = Condition::default();
+        assert!(task::block_on(is_met(&condition, None, Path::new("."))).unwrap());
+    }
+
+    #[test]
+    fn test_is_env_condition_met() {
+        let get_var = |name: &str| match name {
+            "CI" => Some(OsString::from("yes")),
+            "EMPTY" => Some(OsString::new()),
+            _ => None,
+        };
+        let is_met = |env_condition| is_env_condition_met(&env_condition, get_var);
+
+        assert!(is_met(EnvCondition::NonEmpty("CI".to_string())));
+        assert!(!is_met(EnvCondition::NonEmpty("EMPTY".to_string())));
+        assert!(!is_met(EnvCondition::NonEmpty("UNDEFINED".to_string())));
+        assert!(is_met(EnvCondition::Equals(
+            "CI".to_string(),
+            "yes".to_string()
+        )));
+        assert!(!is_met(EnvCondition::Equals(
+            "CI".to_string(),
+            "no".to_string()
+        )));
+        assert!(!is_met(EnvCondition::Equals(
+            "UNDEFINED".to_string(),
+            "".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_is_met_with_os_condition() {
+        let condition = Condition {
+            os: vec![std::env::consts::OS.to_string()],
+            ..Default::default()
+        };
+        assert!(task::block_on(is_met(&condition, None, Path::new("."))).unwrap());
+
+        let condition = Condition {
+            os: vec!["plan9".to_string()],
+            ..Default::default()
+        };
+        assert!(!task::block_on(is_met(&condition, None, Path::new("."))).unwrap());
+    }
+
+    #[test]
+    fn test_is_met_with_cmd_condition() {
+        let condition = Condition {
+            cmd: Some("exit 1".to_string()),
+            ..Default::default()
+        };
+        assert!(!task::block_on(is_met(&condition, None, Path::new("."))).unwrap());
+    }
+
+    #[test]
+    fn test_is_met_with_cmd_condition_and_shell() {
+        let condition = Condition {
+            cmd: Some("[[ -n \"${BASH_VERSION}\" ]]".to_string()),
+            ..Default::default()
+        };
+        let bash = Shell {
+            program: "bash".to_string(),
+            args: vec!["-c".to_string()],
+        };
+        assert!(task::block_on(is_met(&condition, Some(&bash), Path::new("."))).unwrap());
+    }
+}
@@ -0,0 +1,312 @@
+use super::target_actor::{ActorInputMessage, ExecutionKind};
+use crate::domain::{Target, TargetId};
+use std::collections::{HashMap, HashSet};
+
+/// Keeps track of the finalizers of the targets, in order to execute them once these targets are done.
+#[derive(Default)]
+pub struct Finalizers {
+    /// Finalizers which have not been executed yet, by finalized target.
+    pending_finalizers: HashMap<TargetId, Vec<TargetId>>,
+    /// Targets which should be done before executing the finalizers of a target:
+    /// the target itself, and all the targets depending on it.
+    finalized_targets: HashMap<TargetId, HashSet<TargetId>>,
+    /// Services are only done when they fail, or when the execution ends.
+    service_targets: HashSet<TargetId>,
+    done_targets: HashSet<TargetId>,
+    started_finalizers: HashSet<TargetId>,
+    running_finalizers: HashSet<TargetId>,
+    failure_causes: Vec<TargetId>,
+}
+
+impl Finalizers {
+    pub fn new(targets: &HashMap<TargetId, Target>) -> Self {
+        let mut dependents = HashMap::<&TargetId, Vec<&TargetId>>::new();
+        for (target_id, target) in targets {
+            for dependency_id in target.requested_dependencies() {
+                dependents.entry(dependency_id).or_default().push(target_id);
+            }
+        }
+
+        let service_targets = targets
+            .iter()
+            .filter(|(_, target)| matches!(target, Target::Service(_)))
+            .map(|(target_id, _)| target_id.clone())
+            .collect();
+
+        let mut pending_finalizers = HashMap::new();
+        let mut finalized_targets = HashMap::new();
+        for (target_id, target) in targets {
+            if let Target::Build(build_target) = target {
+                if build_target.finalizers.is_empty() {
+                    continue;
+                }
+
+                let mut finalized = HashSet::new();
+                let mut to_visit = vec![target_id];
+                while let Some(visited_id) = to_visit.pop() {
+                    if finalized.insert(visited_id.clone()) {
+                        to_visit.extend(dependents.get(visited_id).into_iter().flatten());
+                    }
+                }
+
+                pending_finalizers.insert(target_id.clone(), build_target.finalizers.clone());
+                finalized_targets.insert(target_id.clone(), finalized);
+            }
+        }
+
+        Self {
+            pending_finalizers,
+            finalized_targets,
+            service_targets,
+            ..Self::default()
+        }
+    }
+
+    /// Keeps track of the targets which are done, whether they succeeded or failed.
+    pub fn handle_message(&mut self, msg: &ActorInputMessage) {
+        let (target_id, cause) = match msg {
+            ActorInputMessage::Ok {
+                kind: ExecutionKind::Build,
+                target_id,
+                ..
+            } if !self.service_targets.contains(target_id) => (target_id, None),
+            ActorInputMessage::Failed {
+                kind: ExecutionKind::Build,
+                target_id,
+                cause,
+            } => (target_id, Some(cause)),
+            _ => return,
+        };
+
+        self.done_targets.insert(target_id.clone());
+
+        if self.running_finalizers.remove(target_id) {
+            match cause {
+                None => log::info!("{} - Finalizer success", target_id),
+                Some(cause) => {
+                    log::warn!("{} - Finalizer failed", target_id);
+                    self.failure_causes.push(cause.clone());
+                }
+            }
+        }
+    }
+
+    /// Returns the finalizers which are ready to execute,
+    /// as the targets they finalize and the launched targets depending on them are done.
+    pub fn take_ready_finalizers<F>(&mut self, is_launched: F) -> Vec<TargetId>
+    where
+        F: Fn(&TargetId) -> bool,
+    {
+        let done_targets = &self.done_targets;
+        let finalized_targets = &self.finalized_targets;
+        let ready_target_ids = self
+            .pending_finalizers
+            .keys()
+            .filter(|&target_id| {
+                done_targets.contains(target_id)
+                    && finalized_targets[target_id]
+                        .iter()
+                        .all(|id| done_targets.contains(id) || !is_launched(id))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.start_finalizers(ready_target_ids, is_launched)
+    }
+
+    /// Returns the finalizers of all the launched targets, whether they are done or not.
+    ///
+    /// This should be used when the execution ends early, due to a failure or to an interruption.
+    pub fn take_remaining_finalizers<F>(&mut self, is_launched: F) -> Vec<TargetId>
+    where
+        F: Fn(&TargetId) -> bool,
+    {
+        let launched_target_ids = self
+            .pending_finalizers
+            .keys()
+            .filter(|&target_id| is_launched(target_id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.start_finalizers(launched_target_ids, is_launched)
+    }
+
+    fn start_finalizers<F>(&mut self, target_ids: Vec<TargetId>, is_launched: F) -> Vec<TargetId>
+    where
+        F: Fn(&TargetId) -> bool,
+    {
+        let mut finalizer_ids = Vec::new();
+        for target_id in target_ids {
+            for finalizer_id in self.pending_finalizers.remove(&target_id).unwrap() {
+                // A finalizer which is already part of the build flow is not executed a second time
+                if !is_launched(&finalizer_id)
+                    && self.started_finalizers.insert(finalizer_id.clone())
+                {
+                    self.running_finalizers.insert(finalizer_id.clone());
+                    finalizer_ids.push(finalizer_id);
+                }
+            }
+        }
+
+        finalizer_ids
+    }
+
+    pub fn is_finalizer(&self, target_id: &TargetId) -> bool {
+        self.started_finalizers.contains(target_id)
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.running_finalizers.is_empty()
+    }
+
+    /// Finalizers which were started but are not done yet, sorted by name.
+    pub fn running_finalizers(&self) -> Vec<&TargetId> {
+        let mut running_finalizers = self.running_finalizers.iter().collect::<Vec<_>>();
+        running_finalizers.sort_by_key(|target_id| target_id.to_string());
+        running_finalizers
+    }
+
+    /// Targets whose failure prevented a finalizer from succeeding.
+    pub fn failure_causes(&self) -> &[TargetId] {
+        &self.failure_causes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Finalizers;
+    use crate::domain::{
+        AggregateTarget, BuildTarget, Condition, HashAlgorithm, Resources, Script, Target,
+        TargetId, TargetMetadata,
+    };
+    use crate::engine::target_actor::{ActorInputMessage, ExecutionKind};
+    use async_std::path::PathBuf;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_finalizers_should_wait_for_dependents() {
+        let mut finalizers = Finalizers::new(&build_targets());
+        let is_launched = |target_id: &TargetId| target_id.target_name != "compose_down";
+
+        finalizers.handle_message(&ok("compose_up"));
+        assert!(finalizers.take_ready_finalizers(is_launched).is_empty());
+
+        finalizers.handle_message(&failed("tests"));
+        assert!(finalizers.take_ready_finalizers(is_launched).is_empty());
+
+        finalizers.handle_message(&failed("all"));
+        assert_eq!(
+            finalizers.take_ready_finalizers(is_launched),
+            vec![build_target_id("compose_down")]
+        );
+        assert!(finalizers.is_running());
+        assert_eq!(
+            finalizers.running_finalizers(),
+            vec![&build_target_id("compose_down")]
+        );
+        assert!(finalizers.take_ready_finalizers(is_launched).is_empty());
+
+        finalizers.handle_message(&ok("compose_down"));
+        assert!(!finalizers.is_running());
+        assert!(finalizers.failure_causes().is_empty());
+    }
+
+    #[test]
+    fn test_finalizers_should_ignore_dependents_not_launched() {
+        let mut finalizers = Finalizers::new(&build_targets());
+        let is_launched = |target_id: &TargetId| target_id.target_name == "compose_up";
+
+        finalizers.handle_message(&ok("compose_up"));
+        assert_eq!(
+            finalizers.take_ready_finalizers(is_launched),
+            vec![build_target_id("compose_down")]
+        );
+    }
+
+    #[test]
+    fn test_remaining_finalizers_should_not_wait_for_targets() {
+        let mut finalizers = Finalizers::new(&build_targets());
+        let is_launched = |target_id: &TargetId| target_id.target_name != "compose_down";
+
+        assert_eq!(
+            finalizers.take_remaining_finalizers(is_launched),
+            vec![build_target_id("compose_down")]
+        );
+
+        finalizers.handle_message(&failed("compose_down"));
+        assert!(!finalizers.is_running());
+        assert_eq!(
+            finalizers.failure_causes(),
+            &[build_target_id("compose_down")]
+        );
+    }
+
+    fn build_targets() -> HashMap<TargetId, Target> {
+        let mut compose_up = build_build_target("compose_up", vec![]);
+        compose_up.finalizers = vec![build_target_id("compose_down")];
+
+        vec![
+            Target::Build(compose_up),
+            Target::Build(build_build_target("compose_down", vec![])),
+            Target::Build(build_build_target("tests", vec!["compose_up"])),
+            Target::Aggregate(AggregateTarget {
+                metadata: build_metadata("all", vec!["tests"]),
+            }),
+        ]
+        .into_iter()
+        .map(|target| (target.id().clone(), target))
+        .collect()
+    }
+
+    fn build_build_target(name: &str, dependencies: Vec<&str>) -> BuildTarget {
+        BuildTarget {
+            metadata: build_metadata(name, dependencies),
+            condition: Condition::default(),
+            build_script: Script::Shell(String::new()),
+            shell: None,
+            workdir: PathBuf::new(),
+            env_files: vec![],
+            hash_algorithm: HashAlgorithm::default(),
+            finalizers: vec![],
+            run_if_missing: false,
+            sandbox: false,
+            clean_before_build: false,
+            input: Resources::new(),
+            output: Resources::new(),
+            required_outputs: vec![],
+        }
+    }
+
+    fn build_metadata(name: &str, dependencies: Vec<&str>) -> TargetMetadata {
+        TargetMetadata {
+            id: build_target_id(name),
+            project_dir: PathBuf::new(),
+            dependencies: dependencies.into_iter().map(build_target_id).collect(),
+            optional_dependencies: vec![],
+            after: vec![],
+        }
+    }
+
+    fn build_target_id(name: &str) -> TargetId {
+        TargetId {
+            project_name: None,
+            target_name: name.to_string(),
+        }
+    }
+
+    fn ok(name: &str) -> ActorInputMessage {
+        ActorInputMessage::Ok {
+            kind: ExecutionKind::Build,
+            target_id: build_target_id(name),
+            actual: true,
+        }
+    }
+
+    fn failed(name: &str) -> ActorInputMessage {
+        ActorInputMessage::Failed {
+            kind: ExecutionKind::Build,
+            target_id: build_target_id(name),
+            cause: build_target_id(name),
+        }
+    }
+}
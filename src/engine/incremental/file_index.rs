@@ -0,0 +1,446 @@
+use crate::domain::{self, FilesResource, HashAlgorithm};
+use crate::work_dir;
+use anyhow::{Context, Result};
+use async_std::path::{Path, PathBuf};
+use async_std::task;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, Metadata};
+use std::io::{BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Size of the buffer used to read the files being hashed.
+const READ_BUFFER_SIZE: usize = 128 * 1024;
+
+/// Files modified more recently than this delay are not indexed,
+/// as they could still be modified without their stamp changing.
+const RACY_MODIFICATION_DELAY: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    /// File indexes loaded in memory, by project directory.
+    static ref FILE_INDEXES: Mutex<HashMap<PathBuf, Arc<FileIndex>>> = Mutex::new(HashMap::new());
+}
+
+/// Persistent index of the hashes of the files used as resources by the targets of a project.
+///
+/// The index is shared by all the targets of the project.
+/// A file is only hashed again if its inode, size, modification time or change time
+/// differ from the ones recorded in the index.
+pub struct FileIndex {
+    project_dir: PathBuf,
+    file_path: PathBuf,
+    entries: Mutex<FileIndexEntries>,
+    /// Prevents concurrent saves from writing the same temporary file.
+    save_lock: Mutex<()>,
+}
+
+#[derive(Default)]
+struct FileIndexEntries {
+    entries: HashMap<std::path::PathBuf, FileIndexEntry>,
+    modified: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FileIndexEntry {
+    stamp: FileStamp,
+    hash_algorithm: HashAlgorithm,
+    digest: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+struct FileStamp {
+    inode: u64,
+    size: u64,
+    modified: Duration,
+    changed: Duration,
+}
+
+impl FileIndex {
+    /// Returns the index of the project, loading it from the work directory if needed.
+    pub async fn of_project(project_dir: &Path) -> Arc<Self> {
+        if let Some(file_index) = FILE_INDEXES.lock().unwrap().get(project_dir) {
+            return file_index.clone();
+        }
+
+        let file_path = get_file_index_path(project_dir);
+        let entries = task::spawn_blocking({
+            let file_path = file_path.clone();
+            move || read_entries(&file_path)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            log::debug!("Dropping file index {} (Error: {})", file_path.display(), e);
+            HashMap::new()
+        });
+
+        let file_index = Arc::new(Self {
+            project_dir: project_dir.to_path_buf(),
+            file_path,
+            entries: Mutex::new(FileIndexEntries {
+                entries,
+                modified: false,
+            }),
+            save_lock: Mutex::new(()),
+        });
+
+        FILE_INDEXES
+            .lock()
+            .unwrap()
+            .entry(project_dir.to_path_buf())
+            .or_insert(file_index)
+            .clone()
+    }
+
+    /// Returns the modification time of the file and the digest of its content.
+    pub async fn modified_and_digest(
+        self: &Arc<Self>,
+        file: &Path,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(Duration, Vec<u8>)> {
+        let file_index = self.clone();
+        let file = std::path::PathBuf::from(file);
+        task::spawn_blocking(move || file_index.modified_and_digest_blocking(&file, hash_algorithm))
+            .await
+    }
+
+    fn modified_and_digest_blocking(
+        &self,
+        file: &std::path::Path,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<(Duration, Vec<u8>)> {
+        let metadata = std::fs::metadata(file)
+            .with_context(|| format!("Failed to obtain metadata of file {}", file.display()))?;
+        let stamp = FileStamp::from_metadata(&metadata)
+            .with_context(|| format!("Failed to obtain timestamps of file {}", file.display()))?;
+
+        if let Some(entry) = self.entries.lock().unwrap().entries.get(file) {
+            if entry.stamp == stamp && entry.hash_algorithm == hash_algorithm {
+                return Ok((stamp.modified, entry.digest.clone()));
+            }
+        }
+
+        // The stamp is obtained before reading the file,
+        // so that a modification during the read invalidates the entry
+        let digest = compute_file_digest(file, hash_algorithm)
+            .with_context(|| format!("Failed to compute hash of {}", file.display()))?;
+
+        let mut entries = self.entries.lock().unwrap();
+        if stamp.is_racy() {
+            entries.entries.remove(file);
+        } else {
+            let entry = FileIndexEntry {
+                stamp,
+                hash_algorithm,
+                digest: digest.clone(),
+            };
+            entries.entries.insert(file.to_path_buf(), entry);
+        }
+        entries.modified = true;
+
+        Ok((stamp.modified, digest))
+    }
+
+    /// Drops the entries of the files which belong to the resources but are not listed anymore,
+    /// so that deleted or renamed files do not remain in the index forever.
+    pub fn prune(&self, resources: &[FilesResource], listed_files: &HashSet<PathBuf>) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry_count = entries.entries.len();
+        entries.entries.retain(|file, _| {
+            !resources.iter().any(|resource| {
+                resource.paths.iter().any(|path| file.starts_with(path))
+                    && domain::matches_extensions(file, &resource.extensions)
+            }) || listed_files.contains(Path::new(file))
+        });
+        if entries.entries.len() != entry_count {
+            entries.modified = true;
+        }
+    }
+
+    /// Saves the index in the work directory, unless it has not been modified.
+    pub async fn save(self: &Arc<Self>) -> Result<()> {
+        let file_index = self.clone();
+        task::spawn_blocking(move || file_index.save_blocking()).await
+    }
+
+    fn save_blocking(&self) -> Result<()> {
+        if work_dir::is_read_only(&self.project_dir) {
+            return Ok(());
+        }
+
+        let _save_guard = self.save_lock.lock().unwrap();
+
+        let serialized_entries = {
+            let mut entries = self.entries.lock().unwrap();
+            if !entries.modified {
+                return Ok(());
+            }
+            entries.modified = false;
+
+            bincode::serialize(&entries.entries).with_context(|| {
+                format!(
+                    "Failed to serialize file index {}",
+                    self.file_path.display()
+                )
+            })?
+        };
+
+        let file_path: &std::path::Path = self.file_path.as_path().into();
+        if let Some(work_dir) = file_path.parent() {
+            std::fs::create_dir_all(work_dir).ok();
+        }
+
+        work_dir::write_atomically(file_path, |file| {
+            file.write_all(&serialized_entries)
+                .with_context(|| format!("Failed to write file index {}", file_path.display()))
+        })
+    }
+}
+
+impl FileStamp {
+    #[cfg(unix)]
+    fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(Self {
+            inode: metadata.ino(),
+            size: metadata.len(),
+            modified: duration_since_epoch(metadata.modified()?)?,
+            changed: Duration::new(metadata.ctime().max(0) as u64, metadata.ctime_nsec() as u32),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let changed = match metadata.created() {
+            Ok(created) => duration_since_epoch(created)?,
+            Err(_) => Duration::default(),
+        };
+
+        Ok(Self {
+            inode: 0,
+            size: metadata.len(),
+            modified: duration_since_epoch(metadata.modified()?)?,
+            changed,
+        })
+    }
+
+    /// Whether the file was modified so recently that another modification
+    /// might not change its stamp.
+    fn is_racy(&self) -> bool {
+        match duration_since_epoch(SystemTime::now()) {
+            Ok(now) => self.modified.max(self.changed) + RACY_MODIFICATION_DELAY > now,
+            Err(_) => true,
+        }
+    }
+}
+
+fn duration_since_epoch(time: SystemTime) -> Result<Duration> {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .with_context(|| "Failed to obtain duration since UNIX EPOCH".to_string())
+}
+
+fn get_file_index_path(project_dir: &Path) -> PathBuf {
+    work_dir::get_work_dir_path(project_dir).join("files.index")
+}
+
+fn read_entries(file_path: &Path) -> Result<HashMap<std::path::PathBuf, FileIndexEntry>> {
+    let file_path: &std::path::Path = file_path.into();
+    if !file_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open file index {}", file_path.display()))?;
+    bincode::deserialize_from(BufReader::new(file))
+        .with_context(|| format!("Failed to deserialize file index {}", file_path.display()))
+}
+
+fn compute_file_digest(
+    file_path: &std::path::Path,
+    hash_algorithm: HashAlgorithm,
+) -> Result<Vec<u8>> {
+    let mut hasher = FileHasher::new(hash_algorithm);
+    let mut file = File::open(file_path)
+        .with_context(|| format!("Failed to open file {}", file_path.display()))?;
+
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    loop {
+        let count = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read file {}", file_path.display()))?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+    }
+
+    Ok(hasher.finalize())
+}
+
+enum FileHasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl FileHasher {
+    fn new(hash_algorithm: HashAlgorithm) -> Self {
+        match hash_algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Xxh3(hasher) => hasher.digest().to_be_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileHasher, FileIndex, FileIndexEntry, FileStamp, RACY_MODIFICATION_DELAY};
+    use crate::domain::{FilesResource, HashAlgorithm};
+    use async_std::path::PathBuf;
+    use async_std::task;
+    use std::time::Duration;
+
+    #[test]
+    fn test_file_index_should_be_shared_and_persisted() {
+        task::block_on(async {
+            let project_dir = PathBuf::from(std::env::temp_dir())
+                .join(format!("zinoma-file-index-{}", std::process::id()));
+            let file = project_dir.join("file.txt");
+            std::fs::create_dir_all(&project_dir).unwrap();
+            std::fs::write(&file, "content").unwrap();
+
+            let file_index = FileIndex::of_project(&project_dir).await;
+            let (_, digest) = file_index
+                .modified_and_digest(&file, HashAlgorithm::Blake3)
+                .await
+                .unwrap();
+            assert!(std::sync::Arc::ptr_eq(
+                &file_index,
+                &FileIndex::of_project(&project_dir).await
+            ));
+
+            // Recently modified files are not indexed
+            assert!(file_index.entries.lock().unwrap().entries.is_empty());
+
+            task::sleep(RACY_MODIFICATION_DELAY).await;
+            let (_, indexed_digest) = file_index
+                .modified_and_digest(&file, HashAlgorithm::Blake3)
+                .await
+                .unwrap();
+            assert_eq!(indexed_digest, digest);
+            assert_eq!(file_index.entries.lock().unwrap().entries.len(), 1);
+
+            // Entries computed with another algorithm are not reused
+            let (_, sha256_digest) = file_index
+                .modified_and_digest(&file, HashAlgorithm::Sha256)
+                .await
+                .unwrap();
+            assert_ne!(sha256_digest, digest);
+
+            file_index.save().await.unwrap();
+            let entries = super::read_entries(&file_index.file_path).unwrap();
+            let entry = &entries[std::path::Path::new(&file)];
+            assert_eq!(entry.hash_algorithm, HashAlgorithm::Sha256);
+            assert_eq!(entry.digest, sha256_digest);
+
+            std::fs::remove_dir_all(&project_dir).unwrap();
+        })
+    }
+
+    #[test]
+    fn test_file_index_should_prune_files_not_listed_anymore() {
+        task::block_on(async {
+            let project_dir = PathBuf::from(std::env::temp_dir())
+                .join(format!("zinoma-file-index-prune-{}", std::process::id()));
+            let src_dir = project_dir.join("src");
+            let kept_file = src_dir.join("kept.txt");
+            let deleted_file = src_dir.join("deleted.txt");
+            let other_file = project_dir.join("other.txt");
+
+            let file_index = FileIndex::of_project(&project_dir).await;
+            {
+                let mut entries = file_index.entries.lock().unwrap();
+                for file in &[&kept_file, &deleted_file, &other_file] {
+                    let entry = FileIndexEntry {
+                        stamp: FileStamp {
+                            inode: 0,
+                            size: 0,
+                            modified: Duration::default(),
+                            changed: Duration::default(),
+                        },
+                        hash_algorithm: HashAlgorithm::Blake3,
+                        digest: vec![],
+                    };
+                    entries.entries.insert(file.into(), entry);
+                }
+            }
+
+            let resources = vec![FilesResource {
+                paths: vec![src_dir],
+                extensions: None,
+            }];
+            let listed_files = vec![kept_file.clone()].into_iter().collect();
+            file_index.prune(&resources, &listed_files);
+
+            let entries = file_index.entries.lock().unwrap();
+            assert!(entries.modified);
+            let mut files = entries.entries.keys().collect::<Vec<_>>();
+            files.sort();
+            assert_eq!(
+                files,
+                vec![
+                    std::path::Path::new(&other_file),
+                    std::path::Path::new(&kept_file)
+                ]
+            );
+        })
+    }
+
+    #[test]
+    fn test_file_hasher() {
+        fn hex_digest(hash_algorithm: HashAlgorithm, data: &[u8]) -> String {
+            let mut hasher = FileHasher::new(hash_algorithm);
+            hasher.update(data);
+            hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        }
+
+        assert_eq!(
+            hex_digest(HashAlgorithm::Blake3, b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(
+            hex_digest(HashAlgorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex_digest(HashAlgorithm::Xxh3, b"abc"),
+            format!("{:016x}", xxhash_rust::xxh3::xxh3_64(b"abc"))
+        );
+    }
+}
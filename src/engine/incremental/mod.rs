@@ -1,13 +1,17 @@
+mod file_index;
+pub mod report;
 mod resources_state;
 pub mod storage;
 
 use super::builder::BuildTerminationReport;
 use crate::async_utils::both;
-use crate::domain::{Resources, TargetMetadata};
+use crate::domain::{HashAlgorithm, Resources, TargetMetadata};
 use anyhow::Result;
+use file_index::FileIndex;
 use futures::Future;
-use resources_state::ResourcesState;
+use resources_state::{ListedResources, ResourcesState};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(PartialEq)]
 pub enum IncrementalRunResult {
@@ -20,18 +24,27 @@ pub async fn run<F>(
     target: &TargetMetadata,
     target_input: &Resources,
     target_output: Option<&Resources>,
+    hash_algorithm: HashAlgorithm,
     future: F,
 ) -> Result<IncrementalRunResult>
 where
     F: Future<Output = Result<BuildTerminationReport>>,
 {
-    if env_state_has_not_changed_since_last_successful_execution(
+    let file_index = FileIndex::of_project(&target.project_dir).await;
+    // The input is not expected to change during the build, so it is listed once
+    let target_input = ListedResources::list(target_input, &file_index).await;
+
+    let env_state_has_not_changed = env_state_has_not_changed_since_last_successful_execution(
         target,
-        target_input,
+        &target_input,
         target_output,
+        &file_index,
+        hash_algorithm,
     )
-    .await
-    {
+    .await;
+    save_file_index(target, &file_index).await;
+
+    if env_state_has_not_changed {
         return Ok(IncrementalRunResult::Skipped);
     }
 
@@ -42,7 +55,12 @@ where
     match build_report {
         BuildTerminationReport::Cancelled => Ok(IncrementalRunResult::Cancelled),
         BuildTerminationReport::Completed => {
-            match TargetEnvState::current(target_input, target_output).await {
+            let env_state =
+                TargetEnvState::current(&target_input, target_output, &file_index, hash_algorithm)
+                    .await;
+            save_file_index(target, &file_index).await;
+
+            match env_state {
                 Ok(Some(env_state)) => {
                     if let Err(e) = storage::save_env_state(target, env_state).await {
                         log::warn!(
@@ -65,14 +83,68 @@ where
     }
 }
 
+/// Runs the build unless all the output paths of the target exist.
+///
+/// As opposed to [`run`], no state is recorded: the existence of the outputs is all that matters.
+pub async fn run_if_missing<F>(
+    target: &TargetMetadata,
+    target_output: &Resources,
+    future: F,
+) -> Result<IncrementalRunResult>
+where
+    F: Future<Output = Result<BuildTerminationReport>>,
+{
+    if output_paths_exist(target_output).await {
+        log::debug!("{} - All output paths exist", target);
+        return Ok(IncrementalRunResult::Skipped);
+    }
+
+    match future.await? {
+        BuildTerminationReport::Cancelled => Ok(IncrementalRunResult::Cancelled),
+        BuildTerminationReport::Completed => Ok(IncrementalRunResult::Completed),
+    }
+}
+
+async fn output_paths_exist(target_output: &Resources) -> bool {
+    for path in target_output
+        .files
+        .iter()
+        .flat_map(|resource| &resource.paths)
+    {
+        if !path.exists().await {
+            return false;
+        }
+    }
+
+    true
+}
+
+async fn save_file_index(target: &TargetMetadata, file_index: &Arc<FileIndex>) {
+    if let Err(e) = file_index.save().await {
+        log::warn!("{} - Failed to save file index: {:?}", target, e)
+    }
+}
+
 async fn env_state_has_not_changed_since_last_successful_execution(
     target: &TargetMetadata,
-    target_input: &Resources,
+    target_input: &ListedResources<'_>,
     target_output: Option<&Resources>,
+    file_index: &Arc<FileIndex>,
+    hash_algorithm: HashAlgorithm,
 ) -> bool {
     if let Some(saved_state) = storage::read_saved_target_env_state(target).await {
+        if saved_state.hash_algorithm != hash_algorithm {
+            log::debug!(
+                "{} - Dropping checksums computed with {} instead of {}",
+                target,
+                saved_state.hash_algorithm,
+                hash_algorithm
+            );
+            return false;
+        }
+
         saved_state
-            .eq_current_state(target_input, target_output)
+            .eq_current_state(target_input, target_output, file_index, hash_algorithm)
             .await
     } else {
         false
@@ -81,37 +153,58 @@ async fn env_state_has_not_changed_since_last_successful_execution(
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct TargetEnvState {
+    /// Algorithm which computed the checksums of the files.
+    hash_algorithm: HashAlgorithm,
     input: ResourcesState,
     output: Option<ResourcesState>,
 }
 
 impl TargetEnvState {
     pub async fn current(
-        target_input: &Resources,
+        target_input: &ListedResources<'_>,
         target_output: Option<&Resources>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
     ) -> Result<Option<Self>> {
         if target_input.is_empty() {
             Ok(None)
         } else {
-            let input = ResourcesState::current(target_input).await?;
+            let input = ResourcesState::current(target_input, file_index, hash_algorithm).await?;
             let output = match target_output {
-                Some(target_output) => Some(ResourcesState::current(target_output).await?),
+                Some(target_output) => {
+                    let target_output = ListedResources::list(target_output, file_index).await;
+                    Some(ResourcesState::current(&target_output, file_index, hash_algorithm).await?)
+                }
                 None => None,
             };
 
-            Ok(Some(TargetEnvState { input, output }))
+            Ok(Some(TargetEnvState {
+                hash_algorithm,
+                input,
+                output,
+            }))
         }
     }
 
     pub async fn eq_current_state(
         &self,
-        target_input: &Resources,
+        target_input: &ListedResources<'_>,
         target_output: Option<&Resources>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
     ) -> bool {
-        async fn eq(env_state: Option<&ResourcesState>, resources: Option<&Resources>) -> bool {
+        async fn eq_output(
+            env_state: Option<&ResourcesState>,
+            resources: Option<&Resources>,
+            file_index: &Arc<FileIndex>,
+            hash_algorithm: HashAlgorithm,
+        ) -> bool {
             if let Some(resources) = resources {
                 if let Some(env_state) = env_state {
-                    env_state.eq_current_state(resources).await
+                    let resources = ListedResources::list(resources, file_index).await;
+                    env_state
+                        .eq_current_state(&resources, file_index, hash_algorithm)
+                        .await
                 } else {
                     false
                 }
@@ -121,8 +214,14 @@ impl TargetEnvState {
         }
 
         both(
-            eq(Some(&self.input), Some(target_input)),
-            eq(self.output.as_ref(), target_output),
+            self.input
+                .eq_current_state(target_input, file_index, hash_algorithm),
+            eq_output(
+                self.output.as_ref(),
+                target_output,
+                file_index,
+                hash_algorithm,
+            ),
         )
         .await
     }
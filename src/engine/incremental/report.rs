@@ -0,0 +1,218 @@
+use super::file_index::FileIndex;
+use super::resources_state::{ListedResources, ResourcesState};
+use super::{storage, TargetEnvState};
+use crate::domain::BuildTarget;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// State recorded upon the last successful build of a target, as printed by `zinoma state show`.
+#[derive(Serialize)]
+pub struct StateReport {
+    target: String,
+    format_version: u32,
+    hash_algorithm: String,
+    input: ResourcesReport,
+    output: Option<ResourcesReport>,
+}
+
+#[derive(Serialize)]
+struct ResourcesReport {
+    files: BTreeMap<String, FileReport>,
+    cmds: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    /// Modification time, in seconds since UNIX EPOCH.
+    modified: String,
+    hash: String,
+}
+
+/// Differences between the recorded state of a target and its current state,
+/// as printed by `zinoma state diff`.
+#[derive(Serialize)]
+pub struct StateDiff {
+    target: String,
+    /// Whether the target would be skipped, as its resources did not change.
+    up_to_date: bool,
+    recorded_hash_algorithm: String,
+    current_hash_algorithm: String,
+    input: ResourcesDiff,
+    output: Option<ResourcesDiff>,
+}
+
+#[derive(Serialize, Default)]
+struct ResourcesDiff {
+    added_files: Vec<String>,
+    removed_files: Vec<String>,
+    modified_files: Vec<String>,
+    /// Files whose modification time changed, but not their content.
+    touched_files: Vec<String>,
+    changed_cmds: Vec<String>,
+}
+
+pub async fn show_state(target: &BuildTarget) -> Result<StateReport> {
+    let env_state = read_recorded_state(target).await?;
+
+    Ok(StateReport {
+        target: target.to_string(),
+        format_version: storage::FORMAT_VERSION,
+        hash_algorithm: env_state.hash_algorithm.to_string(),
+        input: ResourcesReport::new(&env_state.input),
+        output: env_state.output.as_ref().map(ResourcesReport::new),
+    })
+}
+
+/// Compares the recorded state of the target with its current state.
+///
+/// The current checksums are computed with the algorithm of the recorded state,
+/// so that the files can be compared even if the algorithm has changed since.
+pub async fn diff_state(target: &BuildTarget) -> Result<StateDiff> {
+    let env_state = read_recorded_state(target).await?;
+    let hash_algorithm = env_state.hash_algorithm;
+    let file_index = FileIndex::of_project(&target.metadata.project_dir).await;
+
+    let tracked_input = target.tracked_input();
+    let tracked_input = ListedResources::list(&tracked_input, &file_index).await;
+    let current_input =
+        ResourcesState::current(&tracked_input, &file_index, hash_algorithm).await?;
+    let input = ResourcesDiff::new(&env_state.input, &current_input);
+    let output = match &env_state.output {
+        Some(recorded_output) => {
+            let output = ListedResources::list(&target.output, &file_index).await;
+            let current_output =
+                ResourcesState::current(&output, &file_index, hash_algorithm).await?;
+            Some(ResourcesDiff::new(recorded_output, &current_output))
+        }
+        None => None,
+    };
+
+    let up_to_date = hash_algorithm == target.hash_algorithm
+        && input.is_empty()
+        && output.as_ref().is_none_or(ResourcesDiff::is_empty);
+
+    Ok(StateDiff {
+        target: target.to_string(),
+        up_to_date,
+        recorded_hash_algorithm: hash_algorithm.to_string(),
+        current_hash_algorithm: target.hash_algorithm.to_string(),
+        input,
+        output,
+    })
+}
+
+async fn read_recorded_state(target: &BuildTarget) -> Result<TargetEnvState> {
+    storage::read_env_state(&target.metadata)
+        .await?
+        .ok_or_else(|| anyhow!("No state is recorded for target {}", target))
+}
+
+impl ResourcesReport {
+    fn new(state: &ResourcesState) -> Self {
+        let files = state
+            .files()
+            .iter()
+            .map(|(file, (modified, digest))| {
+                let file_report = FileReport {
+                    modified: format_modified(modified),
+                    hash: to_hex(digest),
+                };
+                (file.display().to_string(), file_report)
+            })
+            .collect();
+
+        let cmds = state
+            .cmd_outputs()
+            .iter()
+            .map(|(cmd, output)| (cmd.clone(), output.clone()))
+            .collect();
+
+        Self { files, cmds }
+    }
+}
+
+impl ResourcesDiff {
+    fn new(recorded: &ResourcesState, current: &ResourcesState) -> Self {
+        let mut diff = Self::default();
+
+        for (file, (modified, digest)) in current.files() {
+            let file_name = file.display().to_string();
+            match recorded.files().get(file) {
+                None => diff.added_files.push(file_name),
+                Some((_, recorded_digest)) if recorded_digest != digest => {
+                    diff.modified_files.push(file_name)
+                }
+                Some((recorded_modified, _)) if recorded_modified != modified => {
+                    diff.touched_files.push(file_name)
+                }
+                Some(_) => {}
+            }
+        }
+
+        diff.removed_files = recorded
+            .files()
+            .keys()
+            .filter(|&file| !current.files().contains_key(file))
+            .map(|file| file.display().to_string())
+            .collect();
+
+        let recorded_cmds = recorded.cmd_outputs();
+        let current_cmds = current.cmd_outputs();
+        diff.changed_cmds = recorded_cmds
+            .keys()
+            .chain(current_cmds.keys())
+            .filter(|&cmd| recorded_cmds.get(cmd) != current_cmds.get(cmd))
+            .cloned()
+            .collect();
+
+        for entries in [
+            &mut diff.added_files,
+            &mut diff.removed_files,
+            &mut diff.modified_files,
+            &mut diff.touched_files,
+            &mut diff.changed_cmds,
+        ] {
+            entries.sort();
+            entries.dedup();
+        }
+
+        diff
+    }
+
+    /// Whether the resources would be considered unchanged by the incremental engine.
+    fn is_empty(&self) -> bool {
+        self.added_files.is_empty()
+            && self.removed_files.is_empty()
+            && self.modified_files.is_empty()
+            && self.changed_cmds.is_empty()
+    }
+}
+
+fn format_modified(modified: &Duration) -> String {
+    format!("{}.{:09}", modified.as_secs(), modified.subsec_nanos())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_modified, to_hex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_modified() {
+        assert_eq!(
+            format_modified(&Duration::new(1_600_000_000, 42)),
+            "1600000000.000000042"
+        );
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xab]), "000fab");
+    }
+}
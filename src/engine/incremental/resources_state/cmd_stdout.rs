@@ -6,7 +6,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Serialize, Deserialize, PartialEq)]
-pub struct ResourcesState(HashMap<String, String>);
+pub struct ResourcesState(pub HashMap<String, String>);
 
 impl ResourcesState {
     pub async fn current(cmds: &[CmdResource]) -> Result<Self> {
@@ -1,67 +1,92 @@
 use crate::async_utils::all;
-use crate::domain::FilesResource;
+use crate::domain::{FilesResource, HashAlgorithm};
+use crate::engine::incremental::file_index::FileIndex;
 use anyhow::{Context, Result};
-use async_std::fs::File;
-use async_std::io::BufReader;
-use async_std::path::Path;
-use async_std::prelude::*;
-use seahash::SeaHasher;
+use async_std::path::{Path, PathBuf};
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::hash::Hasher;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+/// Maximum number of files hashed concurrently.
+const MAX_CONCURRENT_HASHES: usize = 64;
+
 #[derive(Serialize, Deserialize, PartialEq)]
-pub struct ResourcesState(HashMap<std::path::PathBuf, (Duration, u64)>);
+pub struct ResourcesState(pub HashMap<std::path::PathBuf, (Duration, Vec<u8>)>);
 
-impl ResourcesState {
-    pub async fn current(resources: &[FilesResource]) -> Result<Self> {
-        let files = crate::fs::list_files_in_resources(resources).await;
+/// Lists the files of the resources, and drops the files which are not listed anymore from the index.
+pub async fn list_files(
+    resources: &[FilesResource],
+    file_index: &Arc<FileIndex>,
+) -> HashSet<PathBuf> {
+    let files = crate::fs::list_files_in_resources(resources).await;
+    file_index.prune(resources, &files);
+    files
+}
 
-        let mut state = HashMap::with_capacity(files.len());
+impl ResourcesState {
+    pub async fn current(
+        files: &HashSet<PathBuf>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        let futures = files
+            .iter()
+            .map(|file| async move {
+                let file_state = file_index.modified_and_digest(file, hash_algorithm).await?;
+                Ok::<_, anyhow::Error>((file.into(), file_state))
+            })
+            .collect::<Vec<_>>();
 
-        for file in files {
-            let modified = get_file_modified(&file).await.with_context(|| {
-                format!("Failed to obtain file modified date: {}", file.display())
-            })?;
-            let file_hash = compute_file_hash(&file)
-                .await
-                .with_context(|| format!("Failed to compute hash of {}", file.display()))?;
-            state.insert(file.into(), (modified, file_hash));
-        }
+        let state = futures::stream::iter(futures)
+            .buffer_unordered(MAX_CONCURRENT_HASHES)
+            .try_collect()
+            .await?;
 
         Ok(Self(state))
     }
 
-    pub async fn eq_current_state(&self, resources: &[FilesResource]) -> bool {
-        let files = crate::fs::list_files_in_resources(resources).await;
-
+    pub async fn eq_current_state(
+        &self,
+        files: &HashSet<PathBuf>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
+    ) -> bool {
         if files.len() != self.0.len() {
             return false;
         }
 
-        let futures = files.into_iter().map(|file_path| async move {
-            let std_path: &std::path::Path = file_path.as_path().into();
-            match self.0.get(std_path) {
-                None => false,
-                Some(&(saved_modified, saved_hash)) => match get_file_modified(&file_path).await {
-                    Err(e) => {
-                        log::error!("{:?}", e);
-                        false
-                    }
-                    Ok(modified) => {
-                        modified == saved_modified
-                            || match compute_file_hash(&file_path).await {
-                                Err(e) => {
-                                    log::error!("{:?}", e);
-                                    false
-                                }
-                                Ok(hash) => hash == saved_hash,
+        let futures = files
+            .iter()
+            .map(|file_path| async move {
+                let std_path: &std::path::Path = file_path.as_path().into();
+                match self.0.get(std_path) {
+                    None => false,
+                    Some((saved_modified, saved_digest)) => {
+                        match get_file_modified(file_path).await {
+                            Err(e) => {
+                                log::error!("{:?}", e);
+                                false
+                            }
+                            Ok(modified) => {
+                                modified == *saved_modified
+                                    || match file_index
+                                        .modified_and_digest(file_path, hash_algorithm)
+                                        .await
+                                    {
+                                        Err(e) => {
+                                            log::error!("{:?}", e);
+                                            false
+                                        }
+                                        Ok((_, digest)) => digest == *saved_digest,
+                                    }
                             }
+                        }
                     }
-                },
-            }
-        });
+                }
+            })
+            .collect::<Vec<_>>();
 
         all(futures).await
     }
@@ -87,25 +112,3 @@ async fn get_file_modified(file: &Path) -> Result<Duration> {
             )
         })
 }
-
-async fn compute_file_hash(file_path: &Path) -> Result<u64> {
-    let mut hasher = SeaHasher::default();
-    let file = File::open(file_path)
-        .await
-        .with_context(|| format!("Failed to open file {}", file_path.display()))?;
-    let mut reader = BufReader::new(file);
-
-    let mut buffer = [0; 1024];
-    loop {
-        let count = reader
-            .read(&mut buffer)
-            .await
-            .with_context(|| format!("Failed to read file {}", file_path.display()))?;
-        if count == 0 {
-            break;
-        }
-        Hasher::write(&mut hasher, &buffer[..count]);
-    }
-
-    Ok(hasher.finish())
-}
@@ -1,10 +1,37 @@
 mod cmd_stdout;
 mod fs;
 
-use crate::{async_utils::both, domain::Resources};
+use super::file_index::FileIndex;
+use crate::async_utils::both;
+use crate::domain::{HashAlgorithm, Resources};
 use anyhow::Result;
+use async_std::path::PathBuf;
 use futures::future;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Resources along with the files they contain,
+/// so that the files are listed once for all the states computed during a build.
+pub struct ListedResources<'a> {
+    resources: &'a Resources,
+    files: HashSet<PathBuf>,
+}
+
+impl<'a> ListedResources<'a> {
+    pub async fn list(
+        resources: &'a Resources,
+        file_index: &Arc<FileIndex>,
+    ) -> ListedResources<'a> {
+        let files = fs::list_files(&resources.files, file_index).await;
+        Self { resources, files }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty()
+    }
+}
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct ResourcesState {
@@ -13,10 +40,14 @@ pub struct ResourcesState {
 }
 
 impl ResourcesState {
-    pub async fn current(resources: &Resources) -> Result<Self> {
+    pub async fn current(
+        resources: &ListedResources<'_>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<Self> {
         let (fs, cmd_stdout) = future::join(
-            fs::ResourcesState::current(&resources.files),
-            cmd_stdout::ResourcesState::current(&resources.cmds),
+            fs::ResourcesState::current(&resources.files, file_index, hash_algorithm),
+            cmd_stdout::ResourcesState::current(&resources.resources.cmds),
         )
         .await;
 
@@ -26,11 +57,27 @@ impl ResourcesState {
         })
     }
 
-    pub async fn eq_current_state(&self, resources: &Resources) -> bool {
+    pub async fn eq_current_state(
+        &self,
+        resources: &ListedResources<'_>,
+        file_index: &Arc<FileIndex>,
+        hash_algorithm: HashAlgorithm,
+    ) -> bool {
         both(
-            self.fs.eq_current_state(&resources.files),
-            self.cmd_stdout.eq_current_state(&resources.cmds),
+            self.fs
+                .eq_current_state(&resources.files, file_index, hash_algorithm),
+            self.cmd_stdout.eq_current_state(&resources.resources.cmds),
         )
         .await
     }
+
+    /// Modification time and digest of each file.
+    pub fn files(&self) -> &HashMap<std::path::PathBuf, (Duration, Vec<u8>)> {
+        &self.fs.0
+    }
+
+    /// Output of each command.
+    pub fn cmd_outputs(&self) -> &HashMap<String, String> {
+        &self.cmd_stdout.0
+    }
 }
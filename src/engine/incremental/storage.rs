@@ -1,9 +1,24 @@
 use super::TargetEnvState;
 use crate::domain::TargetMetadata;
 use crate::work_dir;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_std::fs;
 use async_std::{path::PathBuf, task};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+
+/// Prefix of the checksums files, followed by the version of their format.
+const FORMAT_HEADER: &[u8] = b"zinoma-checksums";
+
+/// Version of the format of the checksums files.
+///
+/// It should be incremented whenever the serialized state changes,
+/// along with a migration from the previous version in `deserialize_env_state`.
+/// Files written before version 1 have no header, and cannot be migrated.
+pub const FORMAT_VERSION: u32 = 1;
 
 /// File where the state of the target inputs and outputs are stored upon successful build.
 fn get_checksums_file_path(target: &TargetMetadata) -> PathBuf {
@@ -11,45 +26,128 @@ fn get_checksums_file_path(target: &TargetMetadata) -> PathBuf {
 }
 
 pub async fn read_saved_target_env_state(target: &TargetMetadata) -> Option<TargetEnvState> {
-    let file_path = get_checksums_file_path(target);
-    if !&file_path.exists().await {
-        return None;
-    }
-
-    let result = {
-        let target_id = target.id.clone();
-        let file_path = file_path.clone();
-
-        task::spawn_blocking(move || {
-            let file = std::fs::File::open(&file_path).with_context(|| {
-                format!("Failed to open checksums file {}", &file_path.display())
-            })?;
-            bincode::deserialize_from(file)
-                .with_context(|| format!("Failed to deserialize checksums for {}", target_id))
-        })
-        .await
-    };
+    let result = read_env_state(target).await;
 
     if let Err(e) = &result {
-        log::debug!(
-            "{} - Dropping corrupted checksums file (Error: {})",
-            target,
-            e
-        );
+        if let Some(e) = e.downcast_ref::<UnsupportedFormat>() {
+            log::debug!("{} - Dropping checksums file ({})", target, e);
+        } else {
+            log::debug!(
+                "{} - Dropping corrupted checksums file (Error: {:#})",
+                target,
+                e
+            );
+        }
         if let Err(e) = delete_saved_env_state(target).await {
             log::error!(
-                "{} - Failed to drop corrupted checksum file: {} (Error: {})",
+                "{} - Failed to drop checksums file: {} (Error: {})",
                 target,
-                file_path.display(),
+                get_checksums_file_path(target).display(),
                 e
             )
         }
     }
 
-    result.ok()
+    result.ok().flatten()
+}
+
+/// Reads the state saved upon the last successful build of the target, if any.
+///
+/// States saved with a previous version of the format are migrated to the current one,
+/// except the ones saved before the format was versioned.
+pub async fn read_env_state(target: &TargetMetadata) -> Result<Option<TargetEnvState>> {
+    let file_path = get_checksums_file_path(target);
+    if !&file_path.exists().await {
+        return Ok(None);
+    }
+
+    let target_id = target.id.clone();
+    task::spawn_blocking(move || {
+        let content = std::fs::read(&file_path)
+            .with_context(|| format!("Failed to read checksums file {}", file_path.display()))?;
+
+        let (version, payload) = match content.strip_prefix(FORMAT_HEADER) {
+            Some(content) if content.len() >= 4 => {
+                let (version, payload) = content.split_at(4);
+                (u32::from_le_bytes(version.try_into().unwrap()), payload)
+            }
+            Some(_) => return Err(anyhow!("Truncated header in {}", file_path.display())),
+            None => return Err(unversioned_format_error(&content)),
+        };
+
+        deserialize_env_state(version, payload)
+            .with_context(|| format!("Failed to deserialize checksums for {}", target_id))
+            .map(Some)
+    })
+    .await
+}
+
+/// Deserializes a state saved with the provided format version,
+/// migrating it to the current format if needed.
+fn deserialize_env_state(version: u32, payload: &[u8]) -> Result<TargetEnvState> {
+    match version {
+        FORMAT_VERSION => Ok(bincode::deserialize(payload)?),
+        version => Err(UnsupportedFormat::Version(version).into()),
+    }
+}
+
+/// Checksums files were written without any header before version 1,
+/// with another layout and hash function which cannot be migrated.
+fn unversioned_format_error(content: &[u8]) -> anyhow::Error {
+    match bincode::deserialize::<LegacyTargetEnvState>(content) {
+        Ok(_) => UnsupportedFormat::Legacy.into(),
+        Err(e) => anyhow::Error::new(e).context("Failed to deserialize unversioned checksums"),
+    }
+}
+
+/// Layout of the checksums files written before the format was versioned,
+/// only used to recognize them.
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct LegacyTargetEnvState {
+    input: LegacyResourcesState,
+    output: Option<LegacyResourcesState>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct LegacyResourcesState {
+    fs: HashMap<std::path::PathBuf, (Duration, u64)>,
+    cmd_stdout: HashMap<String, String>,
+}
+
+/// Format of a checksums file which cannot be read by this version of Žinoma.
+#[derive(Debug)]
+enum UnsupportedFormat {
+    /// Unversioned format, written by a version of Žinoma older than the format version 1.
+    Legacy,
+    /// Format version written by a more recent version of Žinoma.
+    Version(u32),
+}
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Legacy => write!(
+                fmt,
+                "Checksums file was written by a previous version of Žinoma, before its format was versioned"
+            ),
+            Self::Version(version) => write!(
+                fmt,
+                "Checksums format version {} is not supported by this version of Žinoma",
+                version
+            ),
+        }
+    }
 }
 
+impl std::error::Error for UnsupportedFormat {}
+
 pub async fn delete_saved_env_state(target: &TargetMetadata) -> Result<()> {
+    if work_dir::is_read_only(&target.project_dir) {
+        return Ok(());
+    }
+
     let checksums_file = get_checksums_file_path(target);
     if checksums_file.exists().await {
         fs::remove_file(&checksums_file).await.with_context(|| {
@@ -64,6 +162,10 @@ pub async fn delete_saved_env_state(target: &TargetMetadata) -> Result<()> {
 }
 
 pub async fn save_env_state(target: &TargetMetadata, env_state: TargetEnvState) -> Result<()> {
+    if work_dir::is_read_only(&target.project_dir) {
+        return Ok(());
+    }
+
     fs::create_dir(work_dir::get_work_dir_path(&target.project_dir))
         .await
         .ok();
@@ -71,10 +173,47 @@ pub async fn save_env_state(target: &TargetMetadata, env_state: TargetEnvState)
     let file_path = get_checksums_file_path(target);
     let target_id = target.id.clone();
     task::spawn_blocking(move || {
-        let file = std::fs::File::create(&file_path)
-            .with_context(|| format!("Failed to create checksums file {}", file_path.display()))?;
-        bincode::serialize_into(file, &env_state)
-            .with_context(|| format!("Failed to serialize checksums for {}", target_id))
+        work_dir::write_atomically(file_path.as_path().into(), |file| {
+            file.write_all(FORMAT_HEADER)
+                .and_then(|_| file.write_all(&FORMAT_VERSION.to_le_bytes()))
+                .with_context(|| {
+                    format!("Failed to write checksums file {}", file_path.display())
+                })?;
+            bincode::serialize_into(file, &env_state)
+                .with_context(|| format!("Failed to serialize checksums for {}", target_id))
+        })
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{unversioned_format_error, UnsupportedFormat};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn test_unversioned_format_error() {
+        let mut legacy_files = HashMap::new();
+        legacy_files.insert(
+            std::path::PathBuf::from("src/main.rs"),
+            (Duration::new(1, 2), 3u64),
+        );
+        let legacy_state = (
+            (legacy_files, HashMap::<String, String>::new()),
+            None::<(
+                HashMap<std::path::PathBuf, (Duration, u64)>,
+                HashMap<String, String>,
+            )>,
+        );
+        let legacy_content = bincode::serialize(&legacy_state).unwrap();
+
+        assert!(matches!(
+            unversioned_format_error(&legacy_content).downcast_ref::<UnsupportedFormat>(),
+            Some(UnsupportedFormat::Legacy)
+        ));
+        assert!(unversioned_format_error(b"Lorem ipsum")
+            .downcast_ref::<UnsupportedFormat>()
+            .is_none());
+    }
+}
@@ -0,0 +1,388 @@
+//! Detection of the files accessed by a build, but not declared in the resources of its target.
+//!
+//! The build script runs under `strace`, which records the files opened by the script and its child processes.
+//! Relative paths are resolved against the working directory of the target,
+//! which is inaccurate for processes changing their own working directory.
+
+use crate::domain::{self, BuildTarget, Resources};
+use crate::{fs, work_dir};
+use anyhow::{Context, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, PartialEq)]
+struct FileAccess {
+    path: PathBuf,
+    kind: AccessKind,
+}
+
+/// Files accessed by the build, relative to the project directory.
+#[derive(Debug, Default, PartialEq)]
+struct UndeclaredAccesses {
+    reads: BTreeSet<PathBuf>,
+    writes: BTreeSet<PathBuf>,
+}
+
+pub fn trace_file_path(target: &BuildTarget) -> async_std::path::PathBuf {
+    let target_name = target
+        .to_string()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    let file_name = format!("zinoma-{}-{}.trace", std::process::id(), target_name);
+    std::env::temp_dir().join(file_name).into()
+}
+
+/// Wraps the command line in `strace`, which records the files opened by the command and its child processes.
+pub fn traced_command_line(
+    trace_file: &async_std::path::Path,
+    command_line: Vec<OsString>,
+) -> Vec<OsString> {
+    let mut traced_command_line = [
+        "strace",
+        "-f",
+        "-qq",
+        "-z",
+        "-e",
+        "trace=open,openat,creat",
+        "-o",
+    ]
+    .iter()
+    .map(OsString::from)
+    .collect::<Vec<_>>();
+    traced_command_line.push(trace_file.into());
+    traced_command_line.push("--".into());
+    traced_command_line.extend(command_line);
+    traced_command_line
+}
+
+/// Warns about the files read outside of the input of the target, and written outside of its output.
+pub async fn report_undeclared_accesses(
+    target: &BuildTarget,
+    trace_file: &async_std::path::Path,
+) -> Result<()> {
+    let trace = async_std::fs::read_to_string(trace_file)
+        .await
+        .with_context(|| format!("Failed to read trace file {}", trace_file.display()))?;
+    let current_dir = std::env::current_dir()?;
+    let project_dir = fs::normalize_path(&current_dir.join(&target.metadata.project_dir));
+    let workdir = fs::normalize_path(&current_dir.join(&target.workdir));
+    let input = absolute_resources(&target.input, &current_dir);
+    let output = absolute_resources(&target.output, &current_dir);
+
+    let accesses = parse_trace(&trace)
+        .into_iter()
+        .map(|access| FileAccess {
+            path: fs::normalize_path(&workdir.join(&access.path)),
+            kind: access.kind,
+        })
+        .filter(|access| access.path.is_file())
+        .collect::<Vec<_>>();
+    let undeclared = find_undeclared_accesses(&accesses, &project_dir, &input, &output);
+
+    if !undeclared.reads.is_empty() {
+        log::warn!(
+            "{} - The build read files which are not declared in its input:\n{}",
+            target,
+            yaml_snippet("input", &undeclared.reads)
+        );
+    }
+    if !undeclared.writes.is_empty() {
+        log::warn!(
+            "{} - The build wrote files which are not declared in its output:\n{}",
+            target,
+            yaml_snippet("output", &undeclared.writes)
+        );
+    }
+
+    Ok(())
+}
+
+/// Parses the `open`, `openat` and `creat` calls recorded by `strace -f`.
+fn parse_trace(trace: &str) -> Vec<FileAccess> {
+    let mut unfinished_calls = HashMap::new();
+    let mut accesses = Vec::new();
+
+    for line in trace.lines() {
+        let (pid, call) = match line.split_once(' ') {
+            Some((pid, call)) if pid.chars().all(|c| c.is_ascii_digit()) => (pid, call.trim()),
+            _ => ("", line),
+        };
+
+        if let Some(call) = call.strip_suffix("<unfinished ...>") {
+            unfinished_calls.insert(pid, call.trim_end().to_string());
+            continue;
+        }
+
+        let call = if call.starts_with("<... ") {
+            match (call.split_once("resumed>"), unfinished_calls.remove(pid)) {
+                (Some((_, end)), Some(start)) => start + end,
+                _ => continue,
+            }
+        } else {
+            call.to_string()
+        };
+
+        if let Some(access) = parse_call(&call) {
+            accesses.push(access);
+        }
+    }
+
+    accesses
+}
+
+fn parse_call(call: &str) -> Option<FileAccess> {
+    let (name, args) = call.split_once('(')?;
+    let (args, result) = args.rsplit_once(") = ")?;
+    if result.split_whitespace().next()?.parse::<i64>().ok()? < 0 {
+        return None;
+    }
+
+    let (relative_to_cwd, args) = match name {
+        "openat" => {
+            let (dir_fd, args) = args.split_once(", ")?;
+            (dir_fd == "AT_FDCWD", args)
+        }
+        "open" | "creat" => (true, args),
+        _ => return None,
+    };
+
+    let (path, flags) = parse_quoted_string(args)?;
+    let path = PathBuf::from(path);
+    if path.is_relative() && !relative_to_cwd {
+        return None;
+    }
+
+    let kind = if name == "creat" {
+        AccessKind::Write
+    } else if flags.contains("O_DIRECTORY") {
+        return None;
+    } else if ["O_WRONLY", "O_RDWR", "O_CREAT", "O_TRUNC"]
+        .iter()
+        .any(|flag| flags.contains(flag))
+    {
+        AccessKind::Write
+    } else {
+        AccessKind::Read
+    };
+
+    Some(FileAccess { path, kind })
+}
+
+/// Parses a string escaped by `strace`, and returns it along with the rest of the input.
+fn parse_quoted_string(input: &str) -> Option<(String, &str)> {
+    let mut bytes = Vec::new();
+    let mut chars = input.strip_prefix('"')?.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = &input[index + 2..];
+                return Some((String::from_utf8_lossy(&bytes).into_owned(), rest));
+            }
+            '\\' => match chars.next()?.1 {
+                'n' => bytes.push(b'\n'),
+                't' => bytes.push(b'\t'),
+                'r' => bytes.push(b'\r'),
+                'v' => bytes.push(0x0b),
+                'f' => bytes.push(0x0c),
+                digit @ '0'..='7' => {
+                    let mut value = digit.to_digit(8)?;
+                    for _ in 0..2 {
+                        match chars.clone().next() {
+                            Some((_, digit @ '0'..='7')) => {
+                                value = value * 8 + digit.to_digit(8)?;
+                                chars.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    bytes.push(value as u8);
+                }
+                c => bytes.extend(c.to_string().as_bytes()),
+            },
+            c => bytes.extend(c.to_string().as_bytes()),
+        }
+    }
+
+    None
+}
+
+fn find_undeclared_accesses(
+    accesses: &[FileAccess],
+    project_dir: &Path,
+    input: &[(Vec<PathBuf>, domain::FileExtensions)],
+    output: &[(Vec<PathBuf>, domain::FileExtensions)],
+) -> UndeclaredAccesses {
+    let written_files = accesses
+        .iter()
+        .filter(|access| access.kind == AccessKind::Write)
+        .map(|access| &access.path)
+        .collect::<BTreeSet<_>>();
+    let mut undeclared = UndeclaredAccesses::default();
+
+    for access in accesses {
+        let relative_path = match access.path.strip_prefix(project_dir) {
+            Ok(relative_path) => relative_path.to_path_buf(),
+            Err(_) => continue,
+        };
+        if work_dir::is_in_work_dir(relative_path.as_path().into()) {
+            continue;
+        }
+
+        match access.kind {
+            AccessKind::Read => {
+                if !written_files.contains(&access.path)
+                    && !is_declared(&access.path, input)
+                    && !is_declared(&access.path, output)
+                {
+                    undeclared.reads.insert(relative_path);
+                }
+            }
+            AccessKind::Write => {
+                if !is_declared(&access.path, output) {
+                    undeclared.writes.insert(relative_path);
+                }
+            }
+        }
+    }
+
+    undeclared
+}
+
+fn is_declared(file: &Path, resources: &[(Vec<PathBuf>, domain::FileExtensions)]) -> bool {
+    resources.iter().any(|(paths, extensions)| {
+        paths.iter().any(|path| {
+            file == path || (file.starts_with(path) && domain::matches_extensions(file, extensions))
+        })
+    })
+}
+
+fn absolute_resources(
+    resources: &Resources,
+    current_dir: &Path,
+) -> Vec<(Vec<PathBuf>, domain::FileExtensions)> {
+    resources
+        .files
+        .iter()
+        .map(|resource| {
+            let paths = resource
+                .paths
+                .iter()
+                .map(|path| fs::normalize_path(&current_dir.join(path)))
+                .collect();
+            (paths, resource.extensions.clone())
+        })
+        .collect()
+}
+
+fn yaml_snippet(resources_key: &str, paths: &BTreeSet<PathBuf>) -> String {
+    let paths = paths
+        .iter()
+        .map(|path| {
+            let path = path.to_string_lossy();
+            if path
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "._-/".contains(c))
+            {
+                path.into_owned()
+            } else {
+                format!("{:?}", path)
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("{}:\n  - paths: [{}]", resources_key, paths.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_undeclared_accesses, parse_trace, yaml_snippet, AccessKind, FileAccess};
+    use std::collections::BTreeSet;
+    use std::path::{Path, PathBuf};
+
+    fn access(path: &str, kind: AccessKind) -> FileAccess {
+        FileAccess {
+            path: PathBuf::from(path),
+            kind,
+        }
+    }
+
+    #[test]
+    fn test_parse_trace() {
+        let trace = r#"101   openat(AT_FDCWD, "/etc/ld.so.cache", O_RDONLY|O_CLOEXEC) = 3
+101   openat(AT_FDCWD, "src", O_RDONLY|O_NONBLOCK|O_CLOEXEC|O_DIRECTORY) = 3
+102   openat(AT_FDCWD, "dist/out.txt", O_WRONLY|O_CREAT|O_TRUNC, 0666 <unfinished ...>
+101   open("caf\303\251 \"menu\".txt", O_RDONLY) = 4
+102   <... openat resumed>) = 3
+101   openat(3, "relative.txt", O_RDONLY) = 5
+101   creat("new.txt", 0644) = 6
+101   openat(AT_FDCWD, "missing.txt", O_RDONLY) = -1 ENOENT (No such file or directory)
+102   +++ exited with 0 +++
+"#;
+
+        assert_eq!(
+            parse_trace(trace),
+            vec![
+                access("/etc/ld.so.cache", AccessKind::Read),
+                access("café \"menu\".txt", AccessKind::Read),
+                access("dist/out.txt", AccessKind::Write),
+                access("new.txt", AccessKind::Write),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_undeclared_accesses() {
+        let project_dir = Path::new("/project");
+        let input = vec![(
+            vec![PathBuf::from("/project/src")],
+            Some(vec![".rs".to_string()].into_iter().collect()),
+        )];
+        let output = vec![(vec![PathBuf::from("/project/dist")], None)];
+        let accesses = vec![
+            access("/usr/lib/libc.so", AccessKind::Read),
+            access("/project/src/main.rs", AccessKind::Read),
+            access("/project/src/notes.txt", AccessKind::Read),
+            access("/project/Cargo.toml", AccessKind::Read),
+            access("/project/.zinoma/files.index", AccessKind::Read),
+            access("/project/dist/app", AccessKind::Write),
+            access("/project/dist/app", AccessKind::Read),
+            access("/project/tmp/cache", AccessKind::Write),
+            access("/project/tmp/cache", AccessKind::Read),
+        ];
+
+        let undeclared = find_undeclared_accesses(&accesses, project_dir, &input, &output);
+
+        assert_eq!(
+            undeclared.reads,
+            vec![PathBuf::from("Cargo.toml"), PathBuf::from("src/notes.txt")]
+                .into_iter()
+                .collect::<BTreeSet<_>>()
+        );
+        assert_eq!(
+            undeclared.writes,
+            vec![PathBuf::from("tmp/cache")]
+                .into_iter()
+                .collect::<BTreeSet<_>>()
+        );
+    }
+
+    #[test]
+    fn test_yaml_snippet() {
+        let paths = vec![PathBuf::from("src/main.rs"), PathBuf::from("my file.txt")]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(
+            yaml_snippet("input", &paths),
+            "input:\n  - paths: [\"my file.txt\", src/main.rs]"
+        );
+    }
+}
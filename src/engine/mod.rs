@@ -1,22 +1,30 @@
 mod builder;
+mod condition;
+mod finalizers;
 pub mod incremental;
+mod io_tracer;
+pub mod notifier;
+mod sandbox;
 mod target_actor;
 mod target_actors;
 mod watcher;
 
 use crate::domain::TargetId;
 use crate::TerminationMessage;
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Error, Result};
 use async_std::channel::Receiver;
 use async_std::prelude::*;
+use finalizers::Finalizers;
 use futures::FutureExt;
-use std::collections::HashSet;
+use notifier::{NotificationTracker, Notifier};
+use std::collections::{HashMap, HashSet};
 use target_actor::{ActorId, ActorInputMessage, ExecutionKind, TargetActorOutputMessage};
 pub use target_actors::TargetActors;
 
 pub async fn run(
     root_target_ids: Vec<TargetId>,
     watch_option: WatchOption,
+    notifier: Notifier,
     target_actors: &mut TargetActors,
     termination_events: Receiver<TerminationMessage>,
     target_actor_output_events: Receiver<TargetActorOutputMessage>,
@@ -28,6 +36,8 @@ pub async fn run(
     match watch_option {
         WatchOption::Enabled => {
             watch(
+                &root_target_ids,
+                notifier,
                 target_actors,
                 termination_events,
                 target_actor_output_events,
@@ -47,28 +57,51 @@ pub async fn run(
 }
 
 async fn watch(
+    root_target_ids: &[TargetId],
+    notifier: Notifier,
     target_actors: &mut TargetActors,
     mut termination_events: Receiver<TerminationMessage>,
     mut target_actor_output_events: Receiver<TargetActorOutputMessage>,
 ) -> Result<()> {
+    let mut notification_tracker = NotificationTracker::new(root_target_ids);
+
     loop {
         futures::select! {
             _ = termination_events.next().fuse() => break,
             target_actor_output = target_actor_output_events.next().fuse() => {
-                match target_actor_output.unwrap() {
+                let target_actor_output = target_actor_output.unwrap();
+                for notification in notification_tracker.handle_output(&target_actor_output) {
+                    notifier.notify(&notification);
+                }
+
+                match target_actor_output {
                     TargetActorOutputMessage::TargetExecutionError(target_id, e) => {
                         log::warn!("{} - {}", target_id, e);
                     },
-                    TargetActorOutputMessage::MessageActor { dest, msg } => {
-                        if let ActorId::Target(target_id) = dest {
-                            target_actors.send(&target_id, msg).await?;
-                        }
+                    TargetActorOutputMessage::MessageActor { dest: ActorId::Target(target_id), msg } => {
+                        target_actors.send(&target_id, msg).await?;
                     }
+                    TargetActorOutputMessage::MessageActor { dest: ActorId::Root, .. } => {}
                 }
             }
         }
     }
 
+    let mut finalizers = target_actors.take_finalizers();
+    let mut execution_errors = HashMap::new();
+    execute_remaining_finalizers(
+        &mut finalizers,
+        target_actors,
+        &mut termination_events,
+        &mut target_actor_output_events,
+        &mut execution_errors,
+    )
+    .await?;
+
+    for (target_id, e) in execution_errors {
+        log::warn!("{} - {:?}", target_id, e);
+    }
+
     Ok(())
 }
 
@@ -83,50 +116,133 @@ async fn execute_once(
     let mut unavailable_root_services = unavailable_root_targets;
     let mut service_root_targets = HashSet::new();
     let mut termination_event_received = false;
+    let mut failure_cause = None;
+    let mut execution_errors = HashMap::new();
+    let mut finalizers = target_actors.take_finalizers();
 
+    // Root services keep running until a termination event is received
     while !(termination_event_received
-        || unavailable_root_services.is_empty() && unavailable_root_builds.is_empty())
+        || failure_cause.is_some()
+        || unavailable_root_services.is_empty()
+            && unavailable_root_builds.is_empty()
+            && service_root_targets.is_empty())
     {
         futures::select! {
             _ = termination_events.next().fuse() => termination_event_received = true,
             target_actor_output = target_actor_output_events.next().fuse() => {
                 match target_actor_output.unwrap() {
                     TargetActorOutputMessage::TargetExecutionError(target_id, e) => {
-                        return Err(e.context(format!("An issue occurred with target {}", target_id)));
+                        // The error is only fatal if it prevents a root target from completing
+                        execution_errors.insert(target_id, e);
                     },
-                    TargetActorOutputMessage::MessageActor { dest, msg } => match dest {
-                        ActorId::Target(target_id) => {
-                            target_actors.send(&target_id, msg).await?;
-                        }
-                        ActorId::Root => match msg {
-                            ActorInputMessage::Ok { kind: ExecutionKind::Build, target_id, .. } => {
-                                unavailable_root_builds.remove(&target_id);
-                            },
-                            ActorInputMessage::Ok { kind: ExecutionKind::Service, target_id, actual } => {
-                                unavailable_root_services.remove(&target_id);
-
-                                if actual {
-                                    service_root_targets.insert(target_id);
-                                }
-                            },
-                            _ => {},
+                    TargetActorOutputMessage::MessageActor { dest, msg } => {
+                        finalizers.handle_message(&msg);
+
+                        match dest {
+                            ActorId::Target(target_id) => {
+                                target_actors.send(&target_id, msg).await?;
+                            }
+                            ActorId::Root => match msg {
+                                ActorInputMessage::Ok { kind: ExecutionKind::Build, target_id, .. } => {
+                                    unavailable_root_builds.remove(&target_id);
+                                },
+                                ActorInputMessage::Ok { kind: ExecutionKind::Service, target_id, actual } => {
+                                    unavailable_root_services.remove(&target_id);
+
+                                    if actual {
+                                        service_root_targets.insert(target_id);
+                                    }
+                                },
+                                ActorInputMessage::Failed { target_id, .. } if finalizers.is_finalizer(&target_id) => {},
+                                ActorInputMessage::Failed { cause, .. } => failure_cause = Some(cause),
+                                _ => {},
+                            }
                         }
                     }
                 }
+
+                for finalizer_id in finalizers.take_ready_finalizers(|target_id| target_actors.is_launched(target_id)) {
+                    target_actors.request_target_execution(&finalizer_id, ExecutionKind::Build).await?;
+                }
             }
         }
     }
 
-    if !termination_event_received && !service_root_targets.is_empty() {
-        // Wait for termination event
-        termination_events
-            .recv()
-            .await
-            .with_context(|| "Failed to listen to termination event".to_string())?;
+    execute_remaining_finalizers(
+        &mut finalizers,
+        target_actors,
+        &mut termination_events,
+        &mut target_actor_output_events,
+        &mut execution_errors,
+    )
+    .await?;
+
+    // A failing finalizer fails the execution, unless it already failed for another reason
+    let result = match failure_cause.or_else(|| finalizers.failure_causes().first().cloned()) {
+        Some(cause) => {
+            let e = execution_errors
+                .remove(&cause)
+                .unwrap_or_else(|| anyhow!("Execution failed"));
+            Err(e.context(format!("An issue occurred with target {}", cause)))
+        }
+        None => Ok(()),
+    };
+
+    for (target_id, e) in execution_errors {
+        log::warn!("{} - {:?}", target_id, e);
+    }
+
+    result
+}
+
+/// Executes the finalizers of the launched targets which were not executed yet,
+/// and waits for all the finalizers to complete.
+///
+/// A termination event received in the meantime (such as a second Ctrl-C) stops waiting,
+/// and fails with the finalizers which are still running.
+async fn execute_remaining_finalizers(
+    finalizers: &mut Finalizers,
+    target_actors: &mut TargetActors,
+    termination_events: &mut Receiver<TerminationMessage>,
+    target_actor_output_events: &mut Receiver<TargetActorOutputMessage>,
+    execution_errors: &mut HashMap<TargetId, Error>,
+) -> Result<()> {
+    for finalizer_id in
+        finalizers.take_remaining_finalizers(|target_id| target_actors.is_launched(target_id))
+    {
+        target_actors
+            .request_target_execution(&finalizer_id, ExecutionKind::Build)
+            .await?;
+    }
+
+    while finalizers.is_running() {
+        futures::select! {
+            _ = termination_events.next().fuse() => {
+                return Err(anyhow!(
+                    "Interrupted finalizers {}",
+                    itertools::join(finalizers.running_finalizers(), ", ")
+                ));
+            },
+            target_actor_output = target_actor_output_events.next().fuse() => {
+                match target_actor_output.unwrap() {
+                    TargetActorOutputMessage::TargetExecutionError(target_id, e) => {
+                        execution_errors.insert(target_id, e);
+                    }
+                    TargetActorOutputMessage::MessageActor { dest, msg } => {
+                        finalizers.handle_message(&msg);
+
+                        if let ActorId::Target(target_id) = dest {
+                            target_actors.send(&target_id, msg).await?;
+                        }
+                    }
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
 #[derive(Copy, Clone)]
 pub enum WatchOption {
     Enabled,
@@ -142,3 +258,20 @@ impl From<bool> for WatchOption {
         }
     }
 }
+
+/// Whether builds run under `strace`, to detect the files they access without declaring them.
+#[derive(Copy, Clone)]
+pub enum TraceIoOption {
+    Enabled,
+    Disabled,
+}
+
+impl From<bool> for TraceIoOption {
+    fn from(value: bool) -> Self {
+        if value {
+            TraceIoOption::Enabled
+        } else {
+            TraceIoOption::Disabled
+        }
+    }
+}
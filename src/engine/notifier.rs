@@ -0,0 +1,323 @@
+use super::target_actor::{ActorId, ActorInputMessage, ExecutionKind, TargetActorOutputMessage};
+use crate::domain::TargetId;
+use crate::run_script;
+use async_std::path::PathBuf;
+use async_std::task;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+
+/// Notifies the user of the state transitions of the build flow in watch mode.
+pub struct Notifier {
+    methods: Vec<NotificationMethod>,
+    dir: PathBuf,
+}
+
+impl Notifier {
+    pub fn new(methods: Vec<NotificationMethod>, dir: PathBuf) -> Self {
+        Self { methods, dir }
+    }
+
+    pub fn notify(&self, notification: &Notification) {
+        for method in &self.methods {
+            match method {
+                NotificationMethod::Bell => write_to_terminal("\x07"),
+                NotificationMethod::Osc9 => {
+                    write_to_terminal(&format!("\x1b]9;{}\x07", notification))
+                }
+                NotificationMethod::Osc777 => {
+                    write_to_terminal(&format!("\x1b]777;notify;Žinoma;{}\x07", notification))
+                }
+                NotificationMethod::Command(cmd) => self.run_command(cmd, notification),
+            }
+        }
+    }
+
+    fn run_command(&self, cmd: &str, notification: &Notification) {
+        let mut command = run_script::build_command(cmd, &self.dir);
+        command
+            .env("ZINOMA_NOTIFICATION_KIND", notification.kind())
+            .env("ZINOMA_NOTIFICATION_MESSAGE", notification.to_string());
+        if let Some(target_id) = notification.target_id() {
+            command.env("ZINOMA_NOTIFICATION_TARGET", target_id.to_string());
+        }
+
+        let cmd = cmd.to_string();
+        task::spawn(async move {
+            match command.status().await {
+                Ok(exit_status) if !exit_status.success() => {
+                    log::warn!("Notification command {} failed with {}", cmd, exit_status)
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Failed to run notification command {}: {}", cmd, e),
+            }
+        });
+    }
+}
+
+fn write_to_terminal(sequence: &str) {
+    let mut stderr = std::io::stderr();
+    if let Err(e) = stderr
+        .write_all(sequence.as_bytes())
+        .and_then(|_| stderr.flush())
+    {
+        log::debug!("Failed to write notification to terminal: {}", e);
+    }
+}
+
+pub enum NotificationMethod {
+    /// Terminal bell (`BEL` character).
+    Bell,
+    /// Desktop notification through the OSC 9 escape sequence (e.g. iTerm2, Windows Terminal).
+    Osc9,
+    /// Desktop notification through the OSC 777 escape sequence (e.g. urxvt, VTE-based terminals).
+    Osc777,
+    /// Shell command, receiving the notification through `ZINOMA_NOTIFICATION_*` environment variables.
+    Command(String),
+}
+
+impl NotificationMethod {
+    pub fn try_parse(method: &str) -> Option<Self> {
+        match method {
+            "bell" => Some(Self::Bell),
+            "osc9" => Some(Self::Osc9),
+            "osc777" => Some(Self::Osc777),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks the failures of the targets and the availability of the root targets in watch mode,
+/// to tell which notifications the messages of the target actors trigger.
+pub struct NotificationTracker {
+    failed_targets: HashSet<TargetId>,
+    unavailable_root_targets: HashMap<ExecutionKind, HashSet<TargetId>>,
+    all_targets_green: bool,
+}
+
+impl NotificationTracker {
+    pub fn new(root_target_ids: &[TargetId]) -> Self {
+        let mut unavailable_root_targets = HashMap::new();
+        for &kind in &[ExecutionKind::Build, ExecutionKind::Service] {
+            let root_target_ids = root_target_ids.iter().cloned().collect::<HashSet<_>>();
+            unavailable_root_targets.insert(kind, root_target_ids);
+        }
+
+        Self {
+            failed_targets: HashSet::new(),
+            unavailable_root_targets,
+            all_targets_green: false,
+        }
+    }
+
+    pub fn handle_output(&mut self, output: &TargetActorOutputMessage) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+
+        match output {
+            TargetActorOutputMessage::TargetExecutionError(target_id, _) => {
+                if self.failed_targets.insert(target_id.clone()) {
+                    notifications.push(Notification::BuildFailed(target_id.clone()));
+                }
+            }
+            TargetActorOutputMessage::MessageActor { dest, msg } => {
+                if let ActorInputMessage::Ok {
+                    target_id,
+                    actual: true,
+                    ..
+                } = msg
+                {
+                    if self.failed_targets.remove(target_id) {
+                        notifications.push(Notification::BuildRecovered(target_id.clone()));
+                    }
+                }
+
+                if let ActorId::Root = dest {
+                    match msg {
+                        ActorInputMessage::Ok {
+                            kind, target_id, ..
+                        } => {
+                            self.unavailable_root_targets
+                                .get_mut(kind)
+                                .unwrap()
+                                .remove(target_id);
+                        }
+                        ActorInputMessage::Invalidated { kind, target_id } => {
+                            self.unavailable_root_targets
+                                .get_mut(kind)
+                                .unwrap()
+                                .insert(target_id.clone());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let targets_green = self.failed_targets.is_empty()
+            && self
+                .unavailable_root_targets
+                .values()
+                .all(HashSet::is_empty);
+        if targets_green && !self.all_targets_green {
+            notifications.push(Notification::AllTargetsGreen);
+        }
+        self.all_targets_green = targets_green;
+
+        notifications
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Notification {
+    BuildFailed(TargetId),
+    BuildRecovered(TargetId),
+    AllTargetsGreen,
+}
+
+impl Notification {
+    fn kind(&self) -> &'static str {
+        match self {
+            Notification::BuildFailed(_) => "failure",
+            Notification::BuildRecovered(_) => "recovery",
+            Notification::AllTargetsGreen => "success",
+        }
+    }
+
+    fn target_id(&self) -> Option<&TargetId> {
+        match self {
+            Notification::BuildFailed(target_id) | Notification::BuildRecovered(target_id) => {
+                Some(target_id)
+            }
+            Notification::AllTargetsGreen => None,
+        }
+    }
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Notification::BuildFailed(target_id) => write!(fmt, "{} - Build failed", target_id),
+            Notification::BuildRecovered(target_id) => {
+                write!(fmt, "{} - Build recovered", target_id)
+            }
+            Notification::AllTargetsGreen => write!(fmt, "All requested targets are green"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Notification, NotificationTracker};
+    use crate::domain::TargetId;
+    use crate::engine::target_actor::{
+        ActorId, ActorInputMessage, ExecutionKind, TargetActorOutputMessage,
+    };
+    use anyhow::anyhow;
+
+    fn target_id(target_name: &str) -> TargetId {
+        TargetId::try_parse(target_name, &None).unwrap()
+    }
+
+    fn ok(dest: ActorId, kind: ExecutionKind, target_name: &str) -> TargetActorOutputMessage {
+        TargetActorOutputMessage::MessageActor {
+            dest,
+            msg: ActorInputMessage::Ok {
+                kind,
+                target_id: target_id(target_name),
+                actual: true,
+            },
+        }
+    }
+
+    fn error(target_name: &str) -> TargetActorOutputMessage {
+        TargetActorOutputMessage::TargetExecutionError(target_id(target_name), anyhow!("Failed"))
+    }
+
+    fn make_root_target_available(tracker: &mut NotificationTracker, target_name: &str) {
+        tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Build, target_name));
+    }
+
+    #[test]
+    fn test_all_targets_green_once_root_targets_are_available() {
+        let mut tracker = NotificationTracker::new(&[target_id("build")]);
+
+        assert_eq!(
+            tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Build, "build")),
+            vec![]
+        );
+        assert_eq!(
+            tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Service, "build")),
+            vec![Notification::AllTargetsGreen]
+        );
+        assert_eq!(
+            tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Service, "build")),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_build_failure_is_notified_once() {
+        let mut tracker = NotificationTracker::new(&[]);
+
+        assert_eq!(
+            tracker.handle_output(&error("build")),
+            vec![Notification::BuildFailed(target_id("build"))]
+        );
+        assert_eq!(tracker.handle_output(&error("build")), vec![]);
+    }
+
+    #[test]
+    fn test_build_recovery() {
+        let mut tracker = NotificationTracker::new(&[target_id("build")]);
+        tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Service, "build"));
+        tracker.handle_output(&error("build"));
+
+        assert_eq!(
+            tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Build, "build")),
+            vec![
+                Notification::BuildRecovered(target_id("build")),
+                Notification::AllTargetsGreen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovery_of_dependency() {
+        let mut tracker = NotificationTracker::new(&[target_id("test")]);
+        tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Service, "test"));
+        tracker.handle_output(&error("build"));
+        make_root_target_available(&mut tracker, "test");
+
+        assert_eq!(
+            tracker.handle_output(&ok(
+                ActorId::Target(target_id("test")),
+                ExecutionKind::Build,
+                "build"
+            )),
+            vec![
+                Notification::BuildRecovered(target_id("build")),
+                Notification::AllTargetsGreen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalidated_root_target_is_green_again_once_available() {
+        let mut tracker = NotificationTracker::new(&[target_id("build")]);
+        tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Service, "build"));
+        make_root_target_available(&mut tracker, "build");
+
+        let invalidated = TargetActorOutputMessage::MessageActor {
+            dest: ActorId::Root,
+            msg: ActorInputMessage::Invalidated {
+                kind: ExecutionKind::Build,
+                target_id: target_id("build"),
+            },
+        };
+        assert_eq!(tracker.handle_output(&invalidated), vec![]);
+        assert_eq!(
+            tracker.handle_output(&ok(ActorId::Root, ExecutionKind::Build, "build")),
+            vec![Notification::AllTargetsGreen]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Sandbox exposing only the declared resources of a target to its build script.
+//!
+//! The build script runs under [bubblewrap](https://github.com/containers/bubblewrap),
+//! in a mount namespace whose root only contains the system directories and the paths of the target.
+
+use crate::domain::{BuildTarget, Resources};
+use crate::fs;
+use anyhow::{anyhow, Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Directories of the system, which are exposed read-only if they exist.
+const SYSTEM_DIRS: &[&str] = &["/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc"];
+
+#[derive(Debug, PartialEq, Clone)]
+enum Mount {
+    ReadOnly(PathBuf),
+    ReadWrite(PathBuf),
+}
+
+impl Mount {
+    fn path(&self) -> &Path {
+        match self {
+            Mount::ReadOnly(path) | Mount::ReadWrite(path) => path,
+        }
+    }
+}
+
+/// Sandbox exposing the input paths of a target read-only, and its output paths read-write.
+///
+/// Output paths which do not exist yet are created as empty placeholders, so that they can be exposed
+/// without exposing their parent directory: a directory if the output resource filters files by extension
+/// or if the path ends with `/`, an empty file otherwise.
+pub struct Sandbox {
+    mounts: Vec<Mount>,
+    workdir: PathBuf,
+    placeholders: Vec<Placeholder>,
+}
+
+/// Output path created by the sandbox, along with its modification time upon creation.
+#[derive(Debug)]
+struct Placeholder {
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+impl Sandbox {
+    pub fn prepare(target: &BuildTarget) -> Result<Self> {
+        if !cfg!(target_os = "linux") {
+            return Err(anyhow!("Sandboxed builds are only supported on Linux"));
+        }
+
+        let current_dir = std::env::current_dir()?;
+        let (mounts, placeholders) = get_mounts(&current_dir, &target.input, &target.output)?;
+        let workdir = fs::normalize_path(&current_dir.join(&target.workdir));
+
+        Ok(Self {
+            mounts,
+            workdir,
+            placeholders,
+        })
+    }
+
+    /// Wraps the command line so that it runs in the sandbox.
+    pub fn command_line(&self, command_line: Vec<OsString>) -> Vec<OsString> {
+        bwrap_command_line(self.mounts.clone(), &self.workdir, command_line)
+    }
+
+    /// Removes the placeholders which the build left untouched,
+    /// so that they are not mistaken for outputs of the build.
+    pub fn remove_unused_placeholders(&self) {
+        for placeholder in &self.placeholders {
+            if placeholder.is_unused() {
+                let result = if placeholder.path.is_dir() {
+                    std::fs::remove_dir(&placeholder.path)
+                } else {
+                    std::fs::remove_file(&placeholder.path)
+                };
+                if let Err(e) = result {
+                    log::debug!(
+                        "Failed to remove placeholder {}: {}",
+                        placeholder.path.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Placeholder {
+    fn create(path: PathBuf, is_dir: bool) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create output directory {}", parent.display())
+            })?;
+        }
+        let result = if is_dir {
+            std::fs::create_dir(&path)
+        } else {
+            std::fs::File::create(&path).map(|_| ())
+        };
+        result.with_context(|| format!("Failed to create output path {}", path.display()))?;
+
+        let modified = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .with_context(|| format!("Failed to obtain modified time of {}", path.display()))?;
+        Ok(Self { path, modified })
+    }
+
+    fn is_unused(&self) -> bool {
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) => {
+                let is_empty = if metadata.is_dir() {
+                    std::fs::read_dir(&self.path)
+                        .map(|mut entries| entries.next().is_none())
+                        .unwrap_or(false)
+                } else {
+                    metadata.len() == 0
+                };
+                is_empty && metadata.modified().ok() == Some(self.modified)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn get_mounts(
+    current_dir: &Path,
+    input: &Resources,
+    output: &Resources,
+) -> Result<(Vec<Mount>, Vec<Placeholder>)> {
+    let absolute_path =
+        |path: &async_std::path::PathBuf| fs::normalize_path(&current_dir.join(path));
+
+    let mut mounts = input
+        .files
+        .iter()
+        .flat_map(|resource| &resource.paths)
+        .map(absolute_path)
+        .filter(|path| path.exists())
+        .map(Mount::ReadOnly)
+        .collect::<Vec<_>>();
+
+    let mut placeholders = vec![];
+    for resource in &output.files {
+        for configured_path in &resource.paths {
+            let path = absolute_path(configured_path);
+            if !path.exists() {
+                let is_dir = resource.extensions.is_some()
+                    || has_trailing_separator(configured_path.as_ref());
+                placeholders.push(Placeholder::create(path.clone(), is_dir)?);
+            }
+            mounts.push(Mount::ReadWrite(path));
+        }
+    }
+
+    Ok((mounts, placeholders))
+}
+
+fn has_trailing_separator(path: &Path) -> bool {
+    path.as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator)
+}
+
+fn bwrap_command_line(
+    mut mounts: Vec<Mount>,
+    workdir: &Path,
+    command_line: Vec<OsString>,
+) -> Vec<OsString> {
+    let mut args = vec![OsString::from("bwrap"), "--die-with-parent".into()];
+    for system_dir in SYSTEM_DIRS {
+        args.extend(
+            ["--ro-bind-try", system_dir, system_dir]
+                .iter()
+                .map(OsString::from),
+        );
+    }
+    args.extend(
+        ["--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]
+            .iter()
+            .map(OsString::from),
+    );
+    args.extend(["--dir".into(), workdir.into()]);
+
+    // Nested paths are mounted last, so that they are not hidden by their ancestors.
+    mounts.sort_by_key(|mount| mount.path().components().count());
+    for mount in mounts {
+        let (flag, path) = match mount {
+            Mount::ReadOnly(path) => ("--ro-bind", path),
+            Mount::ReadWrite(path) => ("--bind", path),
+        };
+        args.extend([flag.into(), path.clone().into(), path.into()]);
+    }
+
+    args.extend(["--chdir".into(), workdir.into(), "--".into()]);
+    args.extend(command_line);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bwrap_command_line, get_mounts, Mount, Sandbox, SYSTEM_DIRS};
+    use crate::domain::{FilesResource, Resources};
+    use std::collections::BTreeSet;
+    use std::ffi::OsString;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_bwrap_command_line() {
+        let mounts = vec![
+            Mount::ReadOnly(PathBuf::from("/project/dist/config.json")),
+            Mount::ReadWrite(PathBuf::from("/project/dist")),
+            Mount::ReadOnly(PathBuf::from("/project/src")),
+        ];
+        let command_line = vec![OsString::from("npm"), OsString::from("run")];
+
+        let args = bwrap_command_line(mounts, Path::new("/project"), command_line);
+
+        let expected_tail = [
+            "--dir",
+            "/project",
+            "--bind",
+            "/project/dist",
+            "/project/dist",
+            "--ro-bind",
+            "/project/src",
+            "/project/src",
+            "--ro-bind",
+            "/project/dist/config.json",
+            "/project/dist/config.json",
+            "--chdir",
+            "/project",
+            "--",
+            "npm",
+            "run",
+        ]
+        .iter()
+        .map(OsString::from)
+        .collect::<Vec<_>>();
+        assert_eq!(args[0], "bwrap");
+        assert_eq!(
+            args.len(),
+            2 + 3 * SYSTEM_DIRS.len() + 6 + expected_tail.len()
+        );
+        assert_eq!(args[args.len() - expected_tail.len()..], expected_tail[..]);
+    }
+
+    #[test]
+    fn test_get_mounts_creates_placeholders_for_missing_outputs() {
+        let project_dir =
+            std::env::temp_dir().join(format!("zinoma-sandbox-{}", std::process::id()));
+        std::fs::create_dir_all(project_dir.join("src")).unwrap();
+
+        let resources = |paths: &[&str], extensions: Option<&str>| Resources {
+            files: vec![FilesResource {
+                paths: paths
+                    .iter()
+                    .map(|path| project_dir.join(path).into())
+                    .collect(),
+                extensions: extensions
+                    .map(|ext| vec![ext.to_string()].into_iter().collect::<BTreeSet<_>>()),
+            }],
+            cmds: vec![],
+        };
+        let input = resources(&["src"], None);
+        let mut output = resources(&["dist/", "bundle.js", "bin/tool"], None);
+        output
+            .files
+            .extend(resources(&["types"], Some(".d.ts")).files);
+
+        let (mounts, placeholders) = get_mounts(Path::new("/"), &input, &output).unwrap();
+
+        // The missing root-level outputs are exposed by themselves, not through the project directory
+        assert_eq!(
+            mounts,
+            vec![
+                Mount::ReadOnly(project_dir.join("src")),
+                Mount::ReadWrite(project_dir.join("dist")),
+                Mount::ReadWrite(project_dir.join("bundle.js")),
+                Mount::ReadWrite(project_dir.join("bin/tool")),
+                Mount::ReadWrite(project_dir.join("types")),
+            ]
+        );
+        assert!(project_dir.join("dist").is_dir());
+        assert!(project_dir.join("bundle.js").is_file());
+        // Outputs without extension, such as binaries, are files unless their path ends with `/`
+        assert!(project_dir.join("bin/tool").is_file());
+        assert!(project_dir.join("types").is_dir());
+
+        std::fs::write(project_dir.join("bundle.js"), "bundle").unwrap();
+        let sandbox = Sandbox {
+            mounts,
+            workdir: project_dir.clone(),
+            placeholders,
+        };
+        sandbox.remove_unused_placeholders();
+        assert!(!project_dir.join("dist").exists());
+        assert!(project_dir.join("bundle.js").exists());
+        assert!(!project_dir.join("bin/tool").exists());
+        assert!(!project_dir.join("types").exists());
+
+        std::fs::remove_dir_all(&project_dir).unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use super::{ActorInputMessage, ExecutionKind, TargetActorHelper};
-use crate::domain::AggregateTarget;
+use crate::domain::{AggregateTarget, TargetId};
 use async_std::prelude::*;
 use futures::FutureExt;
 use std::collections::{HashMap, HashSet};
@@ -28,21 +28,15 @@ impl AggregateTargetActor {
                 message = self.helper.target_actor_input_receiver.next().fuse() => {
                     match message.unwrap() {
                         ActorInputMessage::Ok { kind, target_id, actual } => {
-                            let removed = self.helper.unavailable_dependencies.get_mut(&kind).unwrap().remove(&target_id);
-
-                            if actual {
-                                dependencies.get_mut(&kind).unwrap().insert(target_id);
-                            }
-
-                            if removed && self.helper.unavailable_dependencies[&kind].is_empty() {
-                                let msg = ActorInputMessage::Ok {
-                                    kind,
-                                    target_id: self.helper.target_id.clone(),
-                                    actual: !dependencies[&kind].is_empty(),
-                                };
-                                self.helper.send_to_requesters(kind, msg).await
-                            }
+                            self.handle_dependency_ok(&mut dependencies, kind, target_id, actual).await
                         },
+                        ActorInputMessage::Failed { kind, target_id, cause } => {
+                            if self.helper.is_failure_tolerated(&target_id) {
+                                self.handle_dependency_ok(&mut dependencies, kind, target_id, false).await
+                            } else {
+                                self.helper.notify_failed(kind, cause).await
+                            }
+                        }
                         ActorInputMessage::Invalidated { kind, target_id } => {
                             let inserted = self.helper.unavailable_dependencies.get_mut(&kind).unwrap().insert(target_id.clone());
 
@@ -53,6 +47,7 @@ impl AggregateTargetActor {
                         }
                         ActorInputMessage::Requested { kind, requester } => {
                             let inserted = self.helper.requesters.get_mut(&kind).unwrap().insert(requester.clone());
+                            self.helper.notify_failure_to(kind, requester.clone()).await;
 
                             if inserted {
                                 let is_first_insertion = self.helper.requesters[&kind].len() == 1;
@@ -70,6 +65,19 @@ impl AggregateTargetActor {
                                 }
                             }
                         }
+                        ActorInputMessage::Awaited { kind, awaiter } => {
+                            self.helper.awaiters.get_mut(&kind).unwrap().insert(awaiter.clone());
+                            self.helper.notify_failure_to(kind, awaiter.clone()).await;
+
+                            if !self.helper.requesters[&kind].is_empty() && self.helper.unavailable_dependencies[&kind].is_empty() {
+                                let msg = ActorInputMessage::Ok {
+                                    kind,
+                                    target_id: self.helper.target_id.clone(),
+                                    actual: !dependencies[&kind].is_empty(),
+                                };
+                                self.helper.send_to_actor(awaiter, msg).await
+                            }
+                        }
                         ActorInputMessage::Unrequested { kind, requester } => {
                             let was_last_requester = self.helper.handle_unrequested(kind, requester);
 
@@ -82,4 +90,33 @@ impl AggregateTargetActor {
             }
         }
     }
+
+    async fn handle_dependency_ok(
+        &mut self,
+        dependencies: &mut HashMap<ExecutionKind, HashSet<TargetId>>,
+        kind: ExecutionKind,
+        target_id: TargetId,
+        actual: bool,
+    ) {
+        let removed = self
+            .helper
+            .unavailable_dependencies
+            .get_mut(&kind)
+            .unwrap()
+            .remove(&target_id);
+
+        if actual {
+            dependencies.get_mut(&kind).unwrap().insert(target_id);
+        }
+
+        if removed && self.helper.unavailable_dependencies[&kind].is_empty() {
+            self.helper.failure_cause = None;
+            let msg = ActorInputMessage::Ok {
+                kind,
+                target_id: self.helper.target_id.clone(),
+                actual: !dependencies[&kind].is_empty(),
+            };
+            self.helper.send_to_requesters(kind, msg).await
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use super::{ActorInputMessage, ExecutionKind, TargetActorHelper};
 use crate::domain::BuildTarget;
-use crate::engine::{builder, incremental};
+use crate::engine::{builder, condition, incremental, TraceIoOption};
 use async_std::{channel, prelude::*};
 use builder::BuildCancellationMessage;
 use futures::future::Fuse;
@@ -9,13 +9,19 @@ use incremental::IncrementalRunResult;
 
 pub struct BuildTargetActor {
     target: BuildTarget,
+    trace_io_option: TraceIoOption,
     helper: TargetActorHelper,
 }
 
 impl BuildTargetActor {
-    pub fn new(target: BuildTarget, target_actor_helper: TargetActorHelper) -> Self {
+    pub fn new(
+        target: BuildTarget,
+        trace_io_option: TraceIoOption,
+        target_actor_helper: TargetActorHelper,
+    ) -> Self {
         Self {
             target,
+            trace_io_option,
             helper: target_actor_helper,
         }
     }
@@ -32,14 +38,45 @@ impl BuildTargetActor {
             {
                 let (build_cancellation_sender, build_cancellation_events) = channel::bounded(1);
                 ongoing_build_cancellation_sender = Some(build_cancellation_sender);
-                let build_future = builder::build_target(&self.target, build_cancellation_events);
+                let target = &self.target;
+                let trace_io_option = self.trace_io_option;
                 ongoing_build_fuse.set(
-                    incremental::run(
-                        &self.target.metadata,
-                        &self.target.input,
-                        Some(&self.target.output),
-                        build_future,
-                    )
+                    async move {
+                        if !condition::is_met(
+                            &target.condition,
+                            target.shell.as_ref(),
+                            &target.metadata.project_dir,
+                        )
+                        .await?
+                        {
+                            return Ok(None);
+                        }
+
+                        let build_future = builder::build_target(
+                            target,
+                            trace_io_option,
+                            build_cancellation_events,
+                        );
+                        if target.run_if_missing {
+                            incremental::run_if_missing(
+                                &target.metadata,
+                                &target.output,
+                                build_future,
+                            )
+                            .await
+                            .map(Some)
+                        } else {
+                            incremental::run(
+                                &target.metadata,
+                                &target.tracked_input(),
+                                Some(&target.output),
+                                target.hash_algorithm,
+                                build_future,
+                            )
+                            .await
+                            .map(Some)
+                        }
+                    }
                     .fuse(),
                 );
 
@@ -65,15 +102,25 @@ impl BuildTargetActor {
                         ActorInputMessage::Ok { kind, target_id, .. } => {
                             self.helper.unavailable_dependencies.get_mut(&kind).unwrap().remove(&target_id);
                         },
+                        ActorInputMessage::Failed { kind, target_id, cause } => {
+                            if self.helper.is_failure_tolerated(&target_id) {
+                                self.helper.unavailable_dependencies.get_mut(&kind).unwrap().remove(&target_id);
+                            } else {
+                                self.helper.notify_failed(ExecutionKind::Build, cause).await
+                            }
+                        }
                         ActorInputMessage::Invalidated { kind, target_id } => {
+                            // `after` targets only order the executions, they do not invalidate this target
+                            let is_after_target = self.helper.after_targets.contains(&target_id);
                             self.helper.unavailable_dependencies.get_mut(&kind).unwrap().insert(target_id);
 
-                            if kind == ExecutionKind::Build {
+                            if kind == ExecutionKind::Build && !is_after_target {
                               self.helper.notify_invalidated(ExecutionKind::Build).await
                             } // TODO Else, if ongoing build, cancel?
                         }
                         ActorInputMessage::Requested { kind: ExecutionKind::Build, requester } => {
-                            let inserted = self.helper.requesters.get_mut(&ExecutionKind::Build).unwrap().insert(requester);
+                            let inserted = self.helper.requesters.get_mut(&ExecutionKind::Build).unwrap().insert(requester.clone());
+                            self.helper.notify_failure_to(ExecutionKind::Build, requester).await;
 
                             if inserted && self.helper.requesters[&ExecutionKind::Build].len() == 1 {
                                 // TODO Eventually, only request deps build (request services when build not skipped)
@@ -81,7 +128,11 @@ impl BuildTargetActor {
                                 self.helper.request_dependencies(ExecutionKind::Service).await;
                             }
                         }
-                        ActorInputMessage::Requested { kind: ExecutionKind::Service, requester } => {
+                        ActorInputMessage::Awaited { kind: ExecutionKind::Build, awaiter } => {
+                            self.helper.handle_awaited(ExecutionKind::Build, awaiter).await
+                        }
+                        ActorInputMessage::Requested { kind: ExecutionKind::Service, requester }
+                        | ActorInputMessage::Awaited { kind: ExecutionKind::Service, awaiter: requester } => {
                             let msg = ActorInputMessage::Ok {
                                 kind: ExecutionKind::Service,
                                 target_id: self.helper.target_id.clone(),
@@ -103,18 +154,22 @@ impl BuildTargetActor {
                     ongoing_build_cancellation_sender = None;
 
                     match build_result {
-                        Err(e) => self.helper.notify_execution_failed(e).await,
-                        Ok(IncrementalRunResult::Skipped) => {
+                        Err(e) => self.helper.notify_execution_failed(ExecutionKind::Build, e).await,
+                        Ok(None) => {
+                            log::info!("{} - Build skipped (condition)", self.target);
+                            self.helper.notify_skipped(ExecutionKind::Build).await;
+                        }
+                        Ok(Some(IncrementalRunResult::Skipped)) => {
                             log::info!("{} - Build skipped (Not Modified)", self.target);
                             self.helper.notify_success(ExecutionKind::Build).await;
                         }
-                        Ok(IncrementalRunResult::Completed) => {
+                        Ok(Some(IncrementalRunResult::Completed)) => {
                             // TODO Why spreading logs between here and builder?
                             self.helper.notify_success(ExecutionKind::Build).await;
 
                             // TODO Eventually, unrequest dependency services
                         }
-                        Ok(IncrementalRunResult::Cancelled) => {
+                        Ok(Some(IncrementalRunResult::Cancelled)) => {
                             // As termination_event_received == true, we will exit the loop
                         },
                     }
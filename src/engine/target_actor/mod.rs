@@ -3,8 +3,8 @@ mod build_target_actor;
 mod service_target_actor;
 mod target_actor_helper;
 
-use super::watcher::{TargetInvalidatedMessage, TargetWatcher};
-use super::WatchOption;
+use super::watcher::{TargetInvalidatedMessage, TargetWatcher, WatchService};
+use super::TraceIoOption;
 use crate::domain::{Target, TargetId};
 use crate::TerminationMessage;
 use aggregate_target_actor::AggregateTargetActor;
@@ -24,6 +24,13 @@ pub enum ActorInputMessage {
         kind: ExecutionKind,
         requester: ActorId,
     },
+    /// Indicates the provided actor waits for the execution of this target, without requesting it.
+    ///
+    /// This message should only be sent to `after` targets, which only run if they are requested otherwise.
+    Awaited {
+        kind: ExecutionKind,
+        awaiter: ActorId,
+    },
     /// Indicates the execution of the build scripts or services behind this target are no more requested by the provided requester.
     ///
     /// This message should only be sent to direct dependencies.
@@ -47,12 +54,23 @@ pub enum ActorInputMessage {
     ///
     /// `actual` should be `true` if there is an actual build/service behind this target.
     ///
-    /// This message should only be sent to build requesters.
+    /// This message should only be sent to requesters and awaiters.
     Ok {
         kind: ExecutionKind,
         target_id: TargetId,
         actual: bool,
     },
+    /// Indicates the build scripts or services behind the provided target failed.
+    ///
+    /// `cause` is the target whose execution actually failed.
+    /// It differs from `target_id` when the failure comes from a dependency.
+    ///
+    /// This message should only be sent to requesters and awaiters.
+    Failed {
+        kind: ExecutionKind,
+        target_id: TargetId,
+        cause: TargetId,
+    },
     /// Indicates the build scripts or services behind the target are not OK anymore.
     ///
     /// The requester should invalidate the previously sent [`Ok`].
@@ -81,7 +99,8 @@ pub enum ActorId {
 
 pub fn launch_target_actor(
     target: Target,
-    watch_option: WatchOption,
+    watch_service: Option<&WatchService>,
+    trace_io_option: TraceIoOption,
     target_actor_output_sender: Sender<TargetActorOutputMessage>,
 ) -> Result<(JoinHandle<()>, TargetActorHandleSet)> {
     let (termination_sender, termination_events) = channel::bounded(1);
@@ -89,11 +108,14 @@ pub fn launch_target_actor(
     let (target_actor_input_sender, target_actor_input_receiver) =
         channel::bounded(crate::DEFAULT_CHANNEL_CAP);
 
-    let watcher = match watch_option {
-        WatchOption::Enabled => {
-            TargetWatcher::new(target.id(), target.input(), &target_invalidated_sender)?
-        }
-        WatchOption::Disabled => None,
+    let watcher = match watch_service {
+        Some(watch_service) => TargetWatcher::new(
+            target.id(),
+            target.watched_input().as_ref(),
+            &target_invalidated_sender,
+            watch_service,
+        )?,
+        None => None,
     };
 
     let target_actor_helper = TargetActorHelper::new(
@@ -106,7 +128,8 @@ pub fn launch_target_actor(
 
     let join_handle = match target {
         Target::Build(build_target) => {
-            let target_actor = BuildTargetActor::new(build_target, target_actor_helper);
+            let target_actor =
+                BuildTargetActor::new(build_target, trace_io_option, target_actor_helper);
             task::spawn(target_actor.run())
         }
         Target::Service(service_target) => {
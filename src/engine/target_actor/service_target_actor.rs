@@ -1,5 +1,6 @@
 use super::{ActorInputMessage, ExecutionKind, TargetActorHelper};
 use crate::domain::ServiceTarget;
+use crate::engine::condition;
 use crate::run_script;
 use anyhow::{Context, Result};
 use async_process::Child;
@@ -28,8 +29,13 @@ impl ServiceTargetActor {
                 self.helper.set_execution_started();
 
                 match self.restart_service().await {
-                    Ok(()) => self.helper.notify_success(ExecutionKind::Service).await,
-                    Err(e) => self.helper.notify_execution_failed(e).await,
+                    Ok(true) => self.helper.notify_success(ExecutionKind::Service).await,
+                    Ok(false) => self.helper.notify_skipped(ExecutionKind::Service).await,
+                    Err(e) => {
+                        self.helper
+                            .notify_execution_failed(ExecutionKind::Service, e)
+                            .await
+                    }
                 }
             }
 
@@ -44,11 +50,24 @@ impl ServiceTargetActor {
                         ActorInputMessage::Ok { kind, target_id, .. } => {
                             self.helper.unavailable_dependencies.get_mut(&kind).unwrap().remove(&target_id);
                         },
+                        ActorInputMessage::Failed { kind, target_id, cause } => {
+                            if self.helper.is_failure_tolerated(&target_id) {
+                                self.helper.unavailable_dependencies.get_mut(&kind).unwrap().remove(&target_id);
+                            } else {
+                                self.helper.notify_failed(ExecutionKind::Service, cause).await
+                            }
+                        }
                         ActorInputMessage::Invalidated { kind, target_id } => {
+                            // `after` targets only order the executions, they do not invalidate this target
+                            let is_after_target = self.helper.after_targets.contains(&target_id);
                             self.helper.unavailable_dependencies.get_mut(&kind).unwrap().insert(target_id);
-                            self.helper.notify_invalidated(ExecutionKind::Service).await
+
+                            if !is_after_target {
+                                self.helper.notify_invalidated(ExecutionKind::Service).await
+                            }
                         }
-                        ActorInputMessage::Requested { kind: ExecutionKind::Build, requester } => {
+                        ActorInputMessage::Requested { kind: ExecutionKind::Build, requester }
+                        | ActorInputMessage::Awaited { kind: ExecutionKind::Build, awaiter: requester } => {
                             let msg = ActorInputMessage::Ok {
                                 kind: ExecutionKind::Build,
                                 target_id: self.helper.target_id.clone(),
@@ -57,13 +76,17 @@ impl ServiceTargetActor {
                             self.helper.send_to_actor(requester, msg).await
                         }
                         ActorInputMessage::Requested { kind: ExecutionKind::Service, requester } => {
-                            let inserted = self.helper.requesters.get_mut(&ExecutionKind::Service).unwrap().insert(requester);
+                            let inserted = self.helper.requesters.get_mut(&ExecutionKind::Service).unwrap().insert(requester.clone());
+                            self.helper.notify_failure_to(ExecutionKind::Service, requester).await;
 
                             if inserted && self.helper.requesters[&ExecutionKind::Service].len() == 1 {
                                 self.helper.request_dependencies(ExecutionKind::Build).await;
                                 self.helper.request_dependencies(ExecutionKind::Service).await;
                             }
                         }
+                        ActorInputMessage::Awaited { kind: ExecutionKind::Service, awaiter } => {
+                            self.helper.handle_awaited(ExecutionKind::Service, awaiter).await
+                        }
                         ActorInputMessage::Unrequested { kind, requester } => {
                             let was_last_requester = self.helper.handle_unrequested(kind, requester);
 
@@ -96,13 +119,31 @@ impl ServiceTargetActor {
         }
     }
 
-    async fn restart_service(&mut self) -> Result<()> {
+    /// Restarts the service, unless its condition is not met.
+    ///
+    /// Returns whether the service was started.
+    async fn restart_service(&mut self) -> Result<bool> {
         self.stop_service().await;
 
+        if !condition::is_met(
+            &self.target.condition,
+            self.target.shell.as_ref(),
+            &self.target.metadata.project_dir,
+        )
+        .await?
+        {
+            log::info!("{} - Service skipped (condition)", self.target.metadata.id);
+            return Ok(false);
+        }
+
         log::info!("{} - Starting service", self.target.metadata.id);
 
-        let mut command =
-            run_script::build_command(&self.target.run_script, &self.target.metadata.project_dir);
+        let mut command = run_script::build_script_command(
+            &self.target.run_script,
+            self.target.shell.as_ref(),
+            &self.target.workdir,
+        );
+        run_script::load_env_files(&mut command, &self.target.env_files)?;
         command.stdout(Stdio::inherit()).stderr(Stdio::inherit());
 
         let service_process = command
@@ -111,6 +152,6 @@ impl ServiceTargetActor {
 
         self.service_process = Some(service_process);
 
-        Ok(())
+        Ok(true)
     }
 }
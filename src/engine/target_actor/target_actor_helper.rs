@@ -4,6 +4,7 @@ use crate::engine::watcher::TargetInvalidatedMessage;
 use crate::TerminationMessage;
 use anyhow::Error;
 use async_std::channel::{Receiver, Sender};
+use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
 
 pub struct TargetActorHelper {
@@ -14,9 +15,20 @@ pub struct TargetActorHelper {
     pub target_actor_output_sender: Sender<TargetActorOutputMessage>,
     pub to_execute: bool,
     pub executed: bool,
+    /// Whether the last execution was an actual one, as opposed to a skipped one.
+    pub actual: bool,
+    /// Dependencies and optional dependencies, which are requested along with this target.
     pub dependencies: Vec<TargetId>,
+    /// `after` targets, which are awaited without being requested.
+    pub after_targets: Vec<TargetId>,
+    /// Dependencies which are allowed to fail: optional dependencies and `after` targets.
+    pub tolerated_failures: HashSet<TargetId>,
+    /// Target whose failure prevents this target from executing, if any.
+    pub failure_cause: Option<TargetId>,
     pub unavailable_dependencies: HashMap<ExecutionKind, HashSet<TargetId>>,
     pub requesters: HashMap<ExecutionKind, HashSet<ActorId>>,
+    /// Actors running after this target, which are notified of its execution without requesting it.
+    pub awaiters: HashMap<ExecutionKind, HashSet<ActorId>>,
 }
 
 impl TargetActorHelper {
@@ -27,16 +39,38 @@ impl TargetActorHelper {
         target_actor_input_receiver: Receiver<ActorInputMessage>,
         target_actor_output_sender: Sender<TargetActorOutputMessage>,
     ) -> Self {
-        let dependencies = target_metadata.dependencies.clone();
+        let tolerated_failures = target_metadata
+            .optional_dependencies
+            .iter()
+            .chain(&target_metadata.after)
+            .filter(|&target_id| !target_metadata.dependencies.contains(target_id))
+            .cloned()
+            .collect::<HashSet<_>>();
+        let dependencies = target_metadata
+            .dependencies
+            .iter()
+            .chain(&target_metadata.optional_dependencies)
+            .cloned()
+            .unique()
+            .collect::<Vec<_>>();
+        let after_targets = target_metadata
+            .after
+            .iter()
+            .filter(|&target_id| !dependencies.contains(target_id))
+            .cloned()
+            .unique()
+            .collect::<Vec<_>>();
 
         let mut unavailable_dependencies = HashMap::new();
-        let dependencies_set: HashSet<_> = dependencies.iter().cloned().collect();
+        let dependencies_set: HashSet<_> =
+            dependencies.iter().chain(&after_targets).cloned().collect();
         unavailable_dependencies.insert(ExecutionKind::Build, dependencies_set.clone());
         unavailable_dependencies.insert(ExecutionKind::Service, dependencies_set);
 
         let mut requesters = HashMap::new();
         requesters.insert(ExecutionKind::Build, HashSet::new());
         requesters.insert(ExecutionKind::Service, HashSet::new());
+        let awaiters = requesters.clone();
 
         Self {
             target_id: target_metadata.id.clone(),
@@ -46,9 +80,14 @@ impl TargetActorHelper {
             target_actor_output_sender,
             to_execute: true,
             executed: false,
+            actual: false,
             dependencies,
+            after_targets,
+            tolerated_failures,
+            failure_cause: None,
             unavailable_dependencies,
             requesters,
+            awaiters,
         }
     }
 
@@ -63,6 +102,7 @@ impl TargetActorHelper {
         if !self.to_execute {
             self.to_execute = true;
             self.executed = false;
+            self.failure_cause = None;
 
             let target_id = self.target_id.clone();
             let msg = ActorInputMessage::Invalidated { kind, target_id };
@@ -73,12 +113,39 @@ impl TargetActorHelper {
     pub fn set_execution_started(&mut self) {
         self.to_execute = false;
         self.executed = false;
+        self.failure_cause = None;
     }
 
-    pub async fn notify_execution_failed(&mut self, e: Error) {
+    pub async fn notify_execution_failed(&mut self, kind: ExecutionKind, e: Error) {
         self.executed = false;
         let msg = TargetActorOutputMessage::TargetExecutionError(self.target_id.clone(), e);
         let _ = self.target_actor_output_sender.send(msg).await;
+
+        self.notify_failed(kind, self.target_id.clone()).await
+    }
+
+    pub fn is_failure_tolerated(&self, dependency_id: &TargetId) -> bool {
+        self.tolerated_failures.contains(dependency_id)
+    }
+
+    /// Notifies the requesters and awaiters that this target can not be executed, as the target `cause` failed.
+    pub async fn notify_failed(&mut self, kind: ExecutionKind, cause: TargetId) {
+        self.failure_cause = Some(cause);
+        for requester in self.requesters[&kind].iter().chain(&self.awaiters[&kind]) {
+            self.notify_failure_to(kind, requester.clone()).await
+        }
+    }
+
+    /// Notifies the requester of the failure of this target, if it failed.
+    pub async fn notify_failure_to(&self, kind: ExecutionKind, requester: ActorId) {
+        if let Some(cause) = &self.failure_cause {
+            let msg = ActorInputMessage::Failed {
+                kind,
+                target_id: self.target_id.clone(),
+                cause: cause.clone(),
+            };
+            self.send_to_actor(requester, msg).await
+        }
     }
 
     pub async fn send_to_actor(&self, dest: ActorId, msg: ActorInputMessage) {
@@ -95,32 +162,72 @@ impl TargetActorHelper {
         }
     }
 
+    /// Sends a message to the requesters and the awaiters of this target.
     pub async fn send_to_requesters(&self, kind: ExecutionKind, msg: ActorInputMessage) {
-        for requester in &self.requesters[&kind] {
+        for requester in self.requesters[&kind].iter().chain(&self.awaiters[&kind]) {
             self.send_to_actor(requester.clone(), msg.clone()).await
         }
     }
 
     pub async fn notify_success(&mut self, kind: ExecutionKind) {
+        self.notify_ok(kind, true).await
+    }
+
+    /// Notifies the requesters that the target was skipped as its condition is not met.
+    pub async fn notify_skipped(&mut self, kind: ExecutionKind) {
+        self.notify_ok(kind, false).await
+    }
+
+    async fn notify_ok(&mut self, kind: ExecutionKind, actual: bool) {
         self.executed = !self.to_execute;
+        self.actual = actual;
 
         if self.executed {
             let target_id = self.target_id.clone();
             let msg = ActorInputMessage::Ok {
                 kind,
                 target_id,
-                actual: true,
+                actual,
             };
             self.send_to_requesters(kind, msg).await
         }
     }
 
+    /// Requests the dependencies, and awaits the `after` targets.
     pub async fn request_dependencies(&self, kind: ExecutionKind) {
         self.send_to_dependencies(ActorInputMessage::Requested {
             kind,
             requester: ActorId::Target(self.target_id.clone()),
         })
         .await;
+
+        for after_target in &self.after_targets {
+            let msg = ActorInputMessage::Awaited {
+                kind,
+                awaiter: ActorId::Target(self.target_id.clone()),
+            };
+            self.send_to_actor(ActorId::Target(after_target.clone()), msg)
+                .await
+        }
+    }
+
+    /// Registers an actor running after this target, and notifies it if this target already executed.
+    pub async fn handle_awaited(&mut self, kind: ExecutionKind, awaiter: ActorId) {
+        self.awaiters
+            .get_mut(&kind)
+            .unwrap()
+            .insert(awaiter.clone());
+
+        if self.failure_cause.is_some() {
+            self.notify_failure_to(kind, awaiter).await
+        } else if self.executed {
+            let msg = ActorInputMessage::Ok {
+                kind,
+                target_id: self.target_id.clone(),
+                actual: self.actual,
+            };
+            self.send_to_actor(awaiter, msg).await
+        }
     }
 
     pub fn handle_unrequested(&mut self, kind: ExecutionKind, requester: ActorId) -> bool {
@@ -1,36 +1,52 @@
+use super::finalizers::Finalizers;
 use super::target_actor::{
     self, ActorId, ActorInputMessage, ExecutionKind, TargetActorHandleSet, TargetActorOutputMessage,
 };
-use super::WatchOption;
+use super::watcher::WatchService;
+use super::{TraceIoOption, WatchOption};
 use crate::domain::{Target, TargetId};
 use crate::TerminationMessage;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_std::channel::Sender;
 use async_std::task::JoinHandle;
 use futures::future;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct TargetActors {
     targets: HashMap<TargetId, Target>,
     target_actor_output_sender: Sender<TargetActorOutputMessage>,
-    watch_option: WatchOption,
+    watch_service: Option<WatchService>,
+    trace_io_option: TraceIoOption,
     target_actor_handles: HashMap<TargetId, TargetActorHandleSet>,
     target_actor_join_handles: Vec<JoinHandle<()>>,
+    finalizers: Finalizers,
 }
 
 impl TargetActors {
     pub fn new(
-        targets: HashMap<TargetId, Target>,
+        mut targets: HashMap<TargetId, Target>,
+        root_target_ids: &[TargetId],
         target_actor_output_sender: Sender<TargetActorOutputMessage>,
         watch_option: WatchOption,
-    ) -> Self {
-        Self {
+        trace_io_option: TraceIoOption,
+    ) -> Result<Self> {
+        resolve_after_targets(&mut targets, root_target_ids)?;
+        let finalizers = Finalizers::new(&targets);
+
+        let watch_service = match watch_option {
+            WatchOption::Enabled => Some(WatchService::new()?),
+            WatchOption::Disabled => None,
+        };
+
+        Ok(Self {
             targets,
             target_actor_output_sender,
-            watch_option,
+            watch_service,
+            trace_io_option,
             target_actor_handles: HashMap::new(),
             target_actor_join_handles: Vec::new(),
-        }
+            finalizers,
+        })
     }
 
     fn get_target_actor_handles<'a>(
@@ -38,10 +54,14 @@ impl TargetActors {
         target_id: &TargetId,
     ) -> Result<&'a TargetActorHandleSet> {
         if !&self.target_actor_handles.contains_key(target_id) {
-            let (owned_target_id, target) = self.targets.remove_entry(target_id).unwrap();
+            let (owned_target_id, target) = self
+                .targets
+                .remove_entry(target_id)
+                .ok_or_else(|| anyhow!("Target {} is not part of the build", target_id))?;
             let (join_handle, handles) = target_actor::launch_target_actor(
                 target,
-                self.watch_option,
+                self.watch_service.as_ref(),
+                self.trace_io_option,
                 self.target_actor_output_sender.clone(),
             )?;
             self.target_actor_handles.insert(owned_target_id, handles);
@@ -62,18 +82,33 @@ impl TargetActors {
     }
 
     pub async fn request_target(&mut self, target_id: &TargetId) -> Result<()> {
-        let handles = self.get_target_actor_handles(target_id)?;
         for &kind in &[ExecutionKind::Build, ExecutionKind::Service] {
-            let build_msg = ActorInputMessage::Requested {
-                kind,
-                requester: ActorId::Root,
-            };
-            let _ = handles.target_actor_input_sender.send(build_msg).await;
+            self.request_target_execution(target_id, kind).await?;
         }
 
         Ok(())
     }
 
+    pub async fn request_target_execution(
+        &mut self,
+        target_id: &TargetId,
+        kind: ExecutionKind,
+    ) -> Result<()> {
+        let msg = ActorInputMessage::Requested {
+            kind,
+            requester: ActorId::Root,
+        };
+        self.send(target_id, msg).await
+    }
+
+    pub fn is_launched(&self, target_id: &TargetId) -> bool {
+        self.target_actor_handles.contains_key(target_id)
+    }
+
+    pub fn take_finalizers(&mut self) -> Finalizers {
+        std::mem::take(&mut self.finalizers)
+    }
+
     pub async fn terminate(self) {
         Self::send_termination_message(&self.target_actor_handles).await;
         future::join_all(self.target_actor_join_handles).await;
@@ -88,3 +123,144 @@ impl TargetActors {
         }
     }
 }
+
+/// Only keeps the `after` targets which are part of the build flow,
+/// and makes sure they do not introduce circular dependencies.
+///
+/// The build flow is made of the root targets and the targets they require.
+/// `after` targets are not requested, so they only run if they are part of it on their own.
+fn resolve_after_targets(
+    targets: &mut HashMap<TargetId, Target>,
+    root_target_ids: &[TargetId],
+) -> Result<()> {
+    let mut build_flow_ids = HashSet::new();
+    let mut pending_ids = root_target_ids.iter().collect::<Vec<_>>();
+    while let Some(target_id) = pending_ids.pop() {
+        if let Some(target) = targets.get(target_id) {
+            if build_flow_ids.insert(target_id.clone()) {
+                pending_ids.extend(target.requested_dependencies());
+            }
+        }
+    }
+
+    for target in targets.values_mut() {
+        target
+            .metadata_mut()
+            .after
+            .retain(|target_id| build_flow_ids.contains(target_id));
+    }
+
+    fn check_target<'a>(
+        targets: &'a HashMap<TargetId, Target>,
+        target_id: &'a TargetId,
+        checked_targets: &mut HashSet<&'a TargetId>,
+        parent_targets: &mut Vec<&'a TargetId>,
+    ) -> Result<()> {
+        if parent_targets.contains(&target_id) {
+            return Err(anyhow!(
+                "Circular dependency: {} -> {}",
+                itertools::join(parent_targets.iter(), " -> "),
+                target_id
+            ));
+        }
+
+        if checked_targets.insert(target_id) {
+            parent_targets.push(target_id);
+            let target = &targets[target_id];
+            for dependency_id in target
+                .requested_dependencies()
+                .chain(&target.metadata().after)
+            {
+                check_target(targets, dependency_id, checked_targets, parent_targets)?;
+            }
+            parent_targets.pop();
+        }
+
+        Ok(())
+    }
+
+    let mut checked_targets = HashSet::new();
+    for target_id in targets.keys() {
+        check_target(targets, target_id, &mut checked_targets, &mut Vec::new())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::resolve_after_targets;
+    use crate::domain::{AggregateTarget, Target, TargetId, TargetMetadata};
+    use async_std::path::PathBuf;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_resolve_after_targets() {
+        let build_targets = || {
+            build_targets(vec![
+                build_target("fmt", vec![], vec![]),
+                build_target("lint", vec![], vec!["fmt"]),
+                build_target("check", vec!["lint"], vec![]),
+            ])
+        };
+
+        let mut targets = build_targets();
+        resolve_after_targets(&mut targets, &[build_target_id("check")]).unwrap();
+        assert!(
+            targets[&build_target_id("lint")]
+                .metadata()
+                .after
+                .is_empty(),
+            "after targets which are not requested should be ignored"
+        );
+
+        let mut targets = build_targets();
+        resolve_after_targets(
+            &mut targets,
+            &[build_target_id("check"), build_target_id("fmt")],
+        )
+        .unwrap();
+        assert_eq!(
+            targets[&build_target_id("lint")].metadata().after,
+            vec![build_target_id("fmt")]
+        );
+    }
+
+    #[test]
+    fn test_resolve_after_targets_with_circular_dependency() {
+        let mut targets = build_targets(vec![
+            build_target("fmt", vec![], vec!["lint"]),
+            build_target("lint", vec![], vec!["fmt"]),
+        ]);
+        let root_target_ids = vec![build_target_id("fmt"), build_target_id("lint")];
+
+        resolve_after_targets(&mut targets, &root_target_ids)
+            .expect_err("Circular after targets should be rejected");
+    }
+
+    fn build_target_id(target_name: &str) -> TargetId {
+        TargetId {
+            project_name: None,
+            target_name: target_name.to_string(),
+        }
+    }
+
+    fn build_target(target_name: &str, dependencies: Vec<&str>, after: Vec<&str>) -> Target {
+        Target::Aggregate(AggregateTarget {
+            metadata: TargetMetadata {
+                id: build_target_id(target_name),
+                project_dir: PathBuf::from("/repo"),
+                dependencies: dependencies.into_iter().map(build_target_id).collect(),
+                optional_dependencies: vec![],
+                after: after.into_iter().map(build_target_id).collect(),
+            },
+        })
+    }
+
+    fn build_targets(targets: Vec<Target>) -> HashMap<TargetId, Target> {
+        targets
+            .into_iter()
+            .map(|target| (target.id().clone(), target))
+            .collect()
+    }
+}
@@ -2,108 +2,83 @@ use crate::domain::{self, Resources, TargetId};
 use crate::work_dir;
 use anyhow::{Context, Error, Result};
 use async_std::channel::Sender;
-use async_std::path::{Path, PathBuf};
+use async_std::path::Path;
 use domain::FileExtensions;
-use notify::{Config, ErrorKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::event::ModifyKind;
+use notify::{Config, ErrorKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::Duration;
 
-pub struct TargetWatcher {
-    _watchers: Vec<RecommendedWatcher>,
+/// Central file system watcher, shared by all the targets.
+///
+/// Each path is registered once with the underlying watcher (nested paths are covered by their ancestors),
+/// and events are fanned out to the targets whose input resources match the modified files.
+///
+/// Input paths which do not exist yet (such as optional env files) are watched through their parent directory.
+/// The watched paths are refreshed whenever a subscribed path is created or removed.
+#[derive(Clone)]
+pub struct WatchService {
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    watcher: Arc<Mutex<RootWatcher>>,
 }
 
-impl TargetWatcher {
-    pub fn new(
-        target_id: &TargetId,
-        target_input: Option<&Resources>,
-        target_invalidated_sender: &Sender<TargetInvalidatedMessage>,
-    ) -> Result<Option<Self>> {
-        if let Some(target_input) = target_input {
-            let mut paths_grouped_by_extensions = HashMap::new();
-            for resource in &target_input.files {
-                paths_grouped_by_extensions
-                    .entry(resource.extensions.clone())
-                    .or_insert_with(HashSet::new)
-                    .extend(resource.paths.iter());
-            }
+impl WatchService {
+    pub fn new() -> Result<Self> {
+        let subscriptions = Arc::new(Mutex::new(Subscriptions::default()));
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+        let watcher = Self::build_immediate_watcher(subscriptions.clone(), refresh_sender)?;
 
-            let watchers = paths_grouped_by_extensions
-                .into_iter()
-                .filter(|(_extensions, paths)| !paths.is_empty())
-                .map(|(extensions, paths)| {
-                    let mut watcher = Self::build_immediate_watcher(
-                        target_id.clone(),
-                        target_invalidated_sender.clone(),
-                        extensions,
-                    )?;
-
-                    for path in paths {
-                        match watcher.watch(path.as_path().into(), RecursiveMode::Recursive) {
-                            Ok(_) => {}
-                            Err(notify::Error {
-                                kind: ErrorKind::PathNotFound,
-                                ..
-                            }) => {
-                                log::warn!(
-                                    "{} - Skipping watch on non-existing path: {}",
-                                    target_id,
-                                    path.display(),
-                                );
-                            }
-                            Err(e) => {
-                                return Err(Error::new(e).context(format!(
-                                    "Error watching path {} for target {}",
-                                    path.display(),
-                                    target_id,
-                                )));
-                            }
-                        }
-                    }
-
-                    Ok(watcher)
-                })
-                .collect::<Result<Vec<_>>>()?;
-
-            return Ok(Some(Self {
-                _watchers: watchers,
-            }));
-        }
+        let watch_service = Self {
+            subscriptions,
+            watcher: Arc::new(Mutex::new(RootWatcher {
+                watcher,
+                watched_paths: HashMap::new(),
+            })),
+        };
+        watch_service.spawn_refresher(refresh_receiver);
 
-        Ok(None)
+        Ok(watch_service)
     }
 
     fn build_immediate_watcher(
-        target_id: TargetId,
-        target_invalidated_sender: Sender<TargetInvalidatedMessage>,
-        extensions: FileExtensions,
+        subscriptions: Arc<Mutex<Subscriptions>>,
+        refresh_sender: mpsc::Sender<()>,
     ) -> Result<RecommendedWatcher> {
         let watcher_config = Config::default().with_poll_interval(Duration::from_millis(100));
         Watcher::new(
             move |result: notify::Result<notify::Event>| {
-                let relevant_files = result
-                    .unwrap()
+                let event = result.unwrap();
+                let creates_or_removes = matches!(
+                    event.kind,
+                    EventKind::Create(_)
+                        | EventKind::Remove(_)
+                        | EventKind::Modify(ModifyKind::Name(_))
+                );
+                let relevant_files = event
                     .paths
                     .into_iter()
                     .filter(|path| {
-                        let path: PathBuf = path.into();
-                        !is_tmp_editor_file(&path)
-                            && !work_dir::is_in_work_dir(&path)
-                            && domain::matches_extensions(path.as_path().into(), &extensions)
+                        let path: &Path = path.as_path().into();
+                        !is_tmp_editor_file(path) && !work_dir::is_in_work_dir(path)
                     })
                     .collect::<Vec<_>>();
 
                 if !relevant_files.is_empty() {
-                    let target_id = target_id.clone();
-                    log::trace!(
-                        "{} - Invalidated by {}",
-                        &target_id,
-                        itertools::join(relevant_files.iter().flat_map(|path| path.to_str()), ", ")
-                    );
-                    if target_invalidated_sender
-                        .try_send(TargetInvalidatedMessage)
-                        .is_err()
-                    {
-                        log::trace!("{} - Target already invalidated. Skipping.", &target_id);
+                    let subscribed_path_changed = {
+                        let subscriptions = subscriptions.lock().unwrap();
+                        subscriptions.dispatch(&relevant_files);
+                        creates_or_removes
+                            && relevant_files
+                                .iter()
+                                .any(|file| subscriptions.is_subscribed_path(file))
+                    };
+
+                    if subscribed_path_changed {
+                        // The underlying watcher cannot be updated from its own event handler
+                        refresh_sender.send(()).ok();
                     }
                 }
             },
@@ -111,6 +86,285 @@ impl TargetWatcher {
         )
         .with_context(|| "Error creating watcher")
     }
+
+    fn subscribe(&self, subscription: Subscription) -> Result<SubscriptionId> {
+        let subscription_id = self.subscriptions.lock().unwrap().insert(subscription);
+
+        if let Err(e) = self.refresh_watched_paths() {
+            self.subscriptions.lock().unwrap().remove(subscription_id);
+            return Err(e);
+        }
+
+        Ok(subscription_id)
+    }
+
+    fn unsubscribe(&self, subscription_id: SubscriptionId) {
+        self.subscriptions.lock().unwrap().remove(subscription_id);
+
+        if let Err(e) = self.refresh_watched_paths() {
+            log::warn!("Failed to refresh watched paths: {:?}", e);
+        }
+    }
+
+    /// Refreshes the watched paths in the background whenever the event handler requests it.
+    ///
+    /// The thread only holds weak references, so that it stops once the service has been dropped.
+    fn spawn_refresher(&self, refresh_receiver: Receiver<()>) {
+        let subscriptions = Arc::downgrade(&self.subscriptions);
+        let watcher = Arc::downgrade(&self.watcher);
+        std::thread::spawn(move || {
+            for () in refresh_receiver {
+                let (subscriptions, watcher) =
+                    match (Weak::upgrade(&subscriptions), Weak::upgrade(&watcher)) {
+                        (Some(subscriptions), Some(watcher)) => (subscriptions, watcher),
+                        _ => break,
+                    };
+
+                if let Err(e) = refresh_watched_paths(&subscriptions, &watcher) {
+                    log::warn!("Failed to refresh watched paths: {:?}", e);
+                }
+            }
+        });
+    }
+
+    fn refresh_watched_paths(&self) -> Result<()> {
+        refresh_watched_paths(&self.subscriptions, &self.watcher)
+    }
+}
+
+/// Aligns the paths registered with the underlying watcher with the paths of the current subscriptions.
+fn refresh_watched_paths(
+    subscriptions: &Mutex<Subscriptions>,
+    watcher: &Mutex<RootWatcher>,
+) -> Result<()> {
+    let (paths, parent_dirs) = {
+        let subscriptions = subscriptions.lock().unwrap();
+        (subscriptions.list_paths(), subscriptions.list_parent_dirs())
+    };
+    let watched_paths = get_watched_paths(paths, parent_dirs);
+    watcher.lock().unwrap().watch_paths(watched_paths)
+}
+
+struct RootWatcher {
+    watcher: RecommendedWatcher,
+    watched_paths: HashMap<PathBuf, RecursiveMode>,
+}
+
+impl RootWatcher {
+    fn watch_paths(&mut self, paths: HashMap<PathBuf, RecursiveMode>) -> Result<()> {
+        for (path, mode) in &self.watched_paths {
+            if paths.get(path) != Some(mode) {
+                log::trace!("Unwatching path {}", path.display());
+                if let Err(e) = self.watcher.unwatch(path) {
+                    log::debug!("Failed to unwatch path {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        for (path, &mode) in &paths {
+            if self.watched_paths.get(path) == Some(&mode) {
+                continue;
+            }
+
+            log::trace!("Watching path {}", path.display());
+            match self.watcher.watch(path, mode) {
+                Ok(_) => {}
+                Err(notify::Error {
+                    kind: ErrorKind::PathNotFound,
+                    ..
+                }) => {
+                    log::warn!("Skipping watch on non-existing path: {}", path.display());
+                }
+                Err(e) => {
+                    return Err(
+                        Error::new(e).context(format!("Error watching path {}", path.display()))
+                    );
+                }
+            }
+        }
+
+        self.watched_paths = paths;
+
+        Ok(())
+    }
+}
+
+type SubscriptionId = usize;
+
+#[derive(Default)]
+struct Subscriptions {
+    subscriptions: HashMap<SubscriptionId, Subscription>,
+    next_subscription_id: SubscriptionId,
+}
+
+impl Subscriptions {
+    fn insert(&mut self, subscription: Subscription) -> SubscriptionId {
+        let subscription_id = self.next_subscription_id;
+        self.next_subscription_id += 1;
+        self.subscriptions.insert(subscription_id, subscription);
+        subscription_id
+    }
+
+    fn remove(&mut self, subscription_id: SubscriptionId) {
+        self.subscriptions.remove(&subscription_id);
+    }
+
+    fn list_paths(&self) -> HashSet<PathBuf> {
+        self.subscriptions
+            .values()
+            .flat_map(|subscription| subscription.filters.iter())
+            .filter(|(path, _extensions)| path.exists())
+            .map(|(path, _extensions)| path.clone())
+            .collect()
+    }
+
+    /// Lists the parent directories of the paths which do not exist.
+    fn list_parent_dirs(&self) -> HashSet<PathBuf> {
+        self.subscriptions
+            .values()
+            .flat_map(|subscription| subscription.filters.iter())
+            .filter(|(path, _extensions)| !path.exists())
+            .filter_map(|(path, _extensions)| path.parent())
+            .map(|parent| parent.to_path_buf())
+            .collect()
+    }
+
+    fn is_subscribed_path(&self, file: &std::path::Path) -> bool {
+        self.subscriptions
+            .values()
+            .flat_map(|subscription| subscription.filters.iter())
+            .any(|(path, _extensions)| path == file)
+    }
+
+    fn dispatch(&self, files: &[PathBuf]) {
+        for subscription in self.subscriptions.values() {
+            let matching_files = files
+                .iter()
+                .filter(|file| subscription.matches(file))
+                .collect::<Vec<_>>();
+
+            if !matching_files.is_empty() {
+                let target_id = &subscription.target_id;
+                log::trace!(
+                    "{} - Invalidated by {}",
+                    target_id,
+                    itertools::join(matching_files.iter().flat_map(|path| path.to_str()), ", ")
+                );
+                if subscription
+                    .target_invalidated_sender
+                    .try_send(TargetInvalidatedMessage)
+                    .is_err()
+                {
+                    log::trace!("{} - Target already invalidated. Skipping.", target_id);
+                }
+            }
+        }
+    }
+}
+
+struct Subscription {
+    target_id: TargetId,
+    filters: Vec<(PathBuf, FileExtensions)>,
+    target_invalidated_sender: Sender<TargetInvalidatedMessage>,
+}
+
+impl Subscription {
+    fn matches(&self, file: &std::path::Path) -> bool {
+        self.filters.iter().any(|(path, extensions)| {
+            file.starts_with(path) && domain::matches_extensions(file, extensions)
+        })
+    }
+}
+
+/// Combines the paths, watched recursively, with the parent directories of the missing paths, watched non-recursively.
+///
+/// Parent directories already covered by a recursive path are not watched again.
+fn get_watched_paths(
+    paths: HashSet<PathBuf>,
+    parent_dirs: HashSet<PathBuf>,
+) -> HashMap<PathBuf, RecursiveMode> {
+    let root_paths = get_root_paths(paths);
+    let parent_dirs = parent_dirs
+        .into_iter()
+        .filter(|dir| {
+            !root_paths
+                .iter()
+                .any(|root_path| dir.starts_with(root_path))
+        })
+        .map(|dir| (dir, RecursiveMode::NonRecursive))
+        .collect::<Vec<_>>();
+
+    root_paths
+        .into_iter()
+        .map(|path| (path, RecursiveMode::Recursive))
+        .chain(parent_dirs)
+        .collect()
+}
+
+/// Keeps only the paths which are not nested in other paths of the set.
+fn get_root_paths(paths: HashSet<PathBuf>) -> HashSet<PathBuf> {
+    paths
+        .iter()
+        .filter(|&path| {
+            !paths
+                .iter()
+                .any(|other| other != path && path.starts_with(other))
+        })
+        .cloned()
+        .collect()
+}
+
+pub struct TargetWatcher {
+    watch_service: WatchService,
+    subscription_id: SubscriptionId,
+}
+
+impl TargetWatcher {
+    pub fn new(
+        target_id: &TargetId,
+        target_input: Option<&Resources>,
+        target_invalidated_sender: &Sender<TargetInvalidatedMessage>,
+        watch_service: &WatchService,
+    ) -> Result<Option<Self>> {
+        if let Some(target_input) = target_input {
+            let mut filters = Vec::new();
+            for resource in &target_input.files {
+                for path in &resource.paths {
+                    let path: PathBuf = path.clone().into();
+                    if path.exists() || path.parent().is_some_and(|parent| parent.is_dir()) {
+                        filters.push((path, resource.extensions.clone()));
+                    } else {
+                        log::warn!(
+                            "{} - Skipping watch on non-existing path: {}",
+                            target_id,
+                            path.display(),
+                        );
+                    }
+                }
+            }
+
+            let subscription_id = watch_service
+                .subscribe(Subscription {
+                    target_id: target_id.clone(),
+                    filters,
+                    target_invalidated_sender: target_invalidated_sender.clone(),
+                })
+                .with_context(|| format!("Error watching input paths of target {}", target_id))?;
+
+            return Ok(Some(Self {
+                watch_service: watch_service.clone(),
+                subscription_id,
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+impl Drop for TargetWatcher {
+    fn drop(&mut self) {
+        self.watch_service.unsubscribe(self.subscription_id);
+    }
 }
 
 fn is_tmp_editor_file(file_path: &Path) -> bool {
@@ -154,3 +408,167 @@ mod is_tmp_editor_file_tests {
         assert!(is_tmp_editor_file(path));
     }
 }
+
+#[cfg(test)]
+mod get_root_paths_tests {
+    use super::get_root_paths;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    fn build_paths(paths: &[&str]) -> HashSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn nested_paths_should_be_covered_by_their_ancestor() {
+        let paths = build_paths(&["/my/project/src", "/my/project/src/main.rs", "/my/project"]);
+        assert_eq!(get_root_paths(paths), build_paths(&["/my/project"]));
+    }
+
+    #[test]
+    fn sibling_paths_should_all_be_roots() {
+        let paths = build_paths(&["/my/project/src", "/my/project/srcs", "/my/project/tests"]);
+        assert_eq!(get_root_paths(paths.clone()), paths);
+    }
+}
+
+#[cfg(test)]
+mod get_watched_paths_tests {
+    use super::get_watched_paths;
+    use notify::RecursiveMode;
+    use std::collections::{HashMap, HashSet};
+    use std::path::PathBuf;
+
+    fn build_paths(paths: &[&str]) -> HashSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn parent_dirs_should_be_watched_non_recursively() {
+        let watched_paths = get_watched_paths(
+            build_paths(&["/my/project/src"]),
+            build_paths(&["/my/project", "/my/project/src/config"]),
+        );
+
+        let expected_paths = vec![
+            (PathBuf::from("/my/project/src"), RecursiveMode::Recursive),
+            (PathBuf::from("/my/project"), RecursiveMode::NonRecursive),
+        ]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+        assert_eq!(watched_paths, expected_paths);
+    }
+}
+
+#[cfg(test)]
+mod subscription_tests {
+    use super::{Subscription, TargetInvalidatedMessage};
+    use crate::domain::TargetId;
+    use async_std::channel;
+    use std::path::{Path, PathBuf};
+
+    fn build_subscription(filters: Vec<(&str, Option<&str>)>) -> Subscription {
+        let (target_invalidated_sender, _) = channel::bounded::<TargetInvalidatedMessage>(1);
+        Subscription {
+            target_id: TargetId {
+                project_name: None,
+                target_name: "my_target".to_string(),
+            },
+            filters: filters
+                .into_iter()
+                .map(|(path, extension)| {
+                    let extensions =
+                        extension.map(|ext| vec![ext.to_string()].into_iter().collect());
+                    (PathBuf::from(path), extensions)
+                })
+                .collect(),
+            target_invalidated_sender,
+        }
+    }
+
+    #[test]
+    fn subscription_should_match_files_in_its_paths() {
+        let subscription = build_subscription(vec![("/my/project/src", None)]);
+        assert!(subscription.matches(Path::new("/my/project/src/main.rs")));
+        assert!(!subscription.matches(Path::new("/my/project/tests/integ.rs")));
+    }
+
+    #[test]
+    fn subscription_should_filter_files_by_extensions() {
+        let subscription = build_subscription(vec![
+            ("/my/project/src", Some(".rs")),
+            ("/my/project/Cargo.toml", None),
+        ]);
+        assert!(subscription.matches(Path::new("/my/project/src/main.rs")));
+        assert!(!subscription.matches(Path::new("/my/project/src/README.md")));
+        assert!(subscription.matches(Path::new("/my/project/Cargo.toml")));
+    }
+}
+
+#[cfg(test)]
+mod watch_service_tests {
+    use super::{TargetInvalidatedMessage, TargetWatcher, WatchService};
+    use crate::domain::{FilesResource, Resources, TargetId};
+    use async_std::channel;
+    use async_std::future;
+    use async_std::task;
+    use std::time::Duration;
+
+    #[test]
+    fn created_input_dir_should_be_watched_recursively() {
+        let project_dir =
+            std::env::temp_dir().join(format!("zinoma-watcher-{}", std::process::id()));
+        std::fs::create_dir_all(&project_dir).unwrap();
+        let generated_dir = project_dir.join("generated");
+        std::fs::remove_dir_all(&generated_dir).ok();
+
+        let target_id = TargetId {
+            project_name: None,
+            target_name: "my_target".to_string(),
+        };
+        let input = Resources {
+            files: vec![FilesResource {
+                paths: vec![generated_dir.clone().into()],
+                extensions: None,
+            }],
+            cmds: vec![],
+        };
+        let (target_invalidated_sender, target_invalidated_events) =
+            channel::bounded::<TargetInvalidatedMessage>(1);
+        let watch_service = WatchService::new().unwrap();
+        let _target_watcher = TargetWatcher::new(
+            &target_id,
+            Some(&input),
+            &target_invalidated_sender,
+            &watch_service,
+        )
+        .unwrap();
+
+        let receive_invalidation = || {
+            task::block_on(future::timeout(
+                Duration::from_secs(5),
+                target_invalidated_events.recv(),
+            ))
+        };
+
+        std::fs::create_dir(&generated_dir).unwrap();
+        assert!(receive_invalidation().is_ok());
+
+        let timeout = std::time::Instant::now() + Duration::from_secs(5);
+        while !watch_service
+            .watcher
+            .lock()
+            .unwrap()
+            .watched_paths
+            .contains_key(&generated_dir)
+        {
+            assert!(std::time::Instant::now() < timeout);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        std::fs::write(generated_dir.join("main.rs"), "fn main() {}").unwrap();
+        assert!(receive_invalidation().is_ok());
+
+        std::fs::remove_dir_all(&project_dir).ok();
+    }
+}
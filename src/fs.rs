@@ -61,3 +61,32 @@ async fn list_files_in_path(path: &Path, extensions: &FileExtensions) -> Vec<Pat
     })
     .await
 }
+
+/// Resolves the `.` and `..` components of the path, without following symbolic links.
+pub fn normalize_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut normalized = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_path;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(
+            normalize_path(Path::new("/project/./frontend/../src/main.rs")),
+            PathBuf::from("/project/src/main.rs")
+        );
+    }
+}
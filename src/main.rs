@@ -1,5 +1,6 @@
 #![recursion_limit = "1024"]
 
+mod affected;
 mod async_utils;
 mod clean;
 mod cli;
@@ -8,18 +9,24 @@ mod domain;
 mod engine;
 mod fs;
 mod run_script;
+mod tags;
 mod work_dir;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use async_ctrlc::CtrlC;
 use async_std::channel::{self, Receiver};
 use async_std::path::PathBuf;
 use async_std::task;
 use clean::clean_target_output_paths;
 use config::{ir, yaml};
-use domain::TargetId;
-use engine::{incremental::storage::delete_saved_env_state, TargetActors};
-use work_dir::remove_work_dir;
+use domain::{Target, TargetId};
+use engine::incremental::{report, storage::delete_saved_env_state};
+use engine::notifier::{NotificationMethod, Notifier};
+use engine::TargetActors;
+use engine::{TraceIoOption, WatchOption};
+use std::collections::HashMap;
+use tags::TagFilter;
+use work_dir::{remove_work_dir, LockPolicy, WorkDirLocks};
 
 #[cfg(all(not(target_env = "msvc"), target_pointer_width = "64"))]
 use jemallocator::Jemalloc;
@@ -31,7 +38,7 @@ static GLOBAL: Jemalloc = Jemalloc;
 pub static DEFAULT_CHANNEL_CAP: usize = 64;
 
 fn main() -> Result<()> {
-    let arg_matches = cli::get_app().get_matches();
+    let arg_matches = cli::get_matches_from(cli::get_app, std::env::args_os());
 
     stderrlog::new()
         .module(module_path!())
@@ -43,40 +50,79 @@ fn main() -> Result<()> {
         std::path::PathBuf::from(arg_matches.value_of(cli::arg::PROJECT_DIR).unwrap());
     let config = yaml::Config::load(&root_project_dir)?;
     let project_dirs = config.get_project_dirs();
-    let config: ir::Config = config.into();
-    let all_target_names = config.list_all_available_target_names();
-
-    let arg_matches = cli::get_app()
-        .mut_arg(cli::arg::TARGETS, |arg| {
-            arg.possible_values(
-                &all_target_names
-                    .iter()
-                    .map(String::as_str)
-                    .collect::<Vec<_>>(),
-            )
-            .required_unless(cli::arg::CLEAN)
-        })
-        .get_matches();
+    let config = ir::Config::try_from(config)?;
+
+    let arg_matches = cli::get_matches_from(
+        || {
+            cli::get_app().mut_arg(cli::arg::TARGETS, |arg| {
+                arg.required_unless(cli::arg::CLEAN)
+                    .required_unless(cli::arg::TAG)
+                    .required_unless(cli::arg::EXCLUDE_TAG)
+            })
+        },
+        std::env::args_os(),
+    );
+
+    if let Some(affected_arg_matches) = arg_matches.subcommand_matches(cli::subcommand::AFFECTED) {
+        return run_affected(
+            affected_arg_matches,
+            config,
+            &root_project_dir,
+            &project_dirs,
+        );
+    }
+
+    if let Some(state_arg_matches) = arg_matches.subcommand_matches(cli::subcommand::STATE) {
+        return run_state(state_arg_matches, config);
+    }
+
+    if arg_matches.is_present(cli::arg::TRACE_IO) && !cfg!(target_os = "linux") {
+        return Err(anyhow!("--trace-io is only supported on Linux"));
+    }
 
     let requested_targets = arg_matches.values_of_lossy(cli::arg::TARGETS);
+    let tag_filter = TagFilter {
+        included_tags: arg_matches
+            .values_of_lossy(cli::arg::TAG)
+            .unwrap_or_default(),
+        excluded_tags: arg_matches
+            .values_of_lossy(cli::arg::EXCLUDE_TAG)
+            .unwrap_or_default(),
+    };
+    let has_selection = requested_targets.is_some() || !tag_filter.is_empty();
 
-    let root_target_ids = if let Some(requested_targets) = &requested_targets {
-        TargetId::try_parse_many(requested_targets, &config.root_project_name).unwrap()
+    let mut root_target_ids = if let Some(requested_targets) = &requested_targets {
+        config.select_targets(requested_targets)?
     } else {
         config.list_all_targets()
     };
+    if !tag_filter.is_empty() {
+        root_target_ids = config.filter_tagged_targets(&root_target_ids, &tag_filter)?;
+    }
 
     let targets = config.try_into_domain_targets(&root_target_ids)?;
 
     task::block_on(async {
+        let _work_dir_locks = lock_work_dirs(&project_dirs, &arg_matches).await?;
+
         if arg_matches.is_present(cli::arg::CLEAN) {
-            if requested_targets.is_some() {
+            if let Some(project_dir) = project_dirs
+                .iter()
+                .find(|project_dir| work_dir::is_read_only(project_dir.as_path().into()))
+            {
+                return Err(anyhow!(
+                    "Can not clean while another process is writing to the '.zinoma' directory of {}",
+                    project_dir.display()
+                ));
+            }
+
+            if has_selection {
                 for target in targets.values() {
                     delete_saved_env_state(target.metadata()).await?;
                 }
             } else {
-                for project_dir in project_dirs {
-                    let project_dir: PathBuf = project_dir.into();
+                for project_dir in &project_dirs {
+                    let project_dir: PathBuf = project_dir.clone().into();
                     remove_work_dir(&project_dir).await?;
                 }
             }
@@ -86,41 +132,169 @@ fn main() -> Result<()> {
             }
         }
 
-        if requested_targets.is_some() {
+        if has_selection {
             let watch_option = arg_matches.is_present(cli::arg::WATCH).into();
-            let termination_events = terminate_on_ctrlc()?;
-
-            let (target_actor_output_sender, target_actor_output_events) =
-                channel::bounded(crate::DEFAULT_CHANNEL_CAP);
-            let mut target_actors =
-                TargetActors::new(targets, target_actor_output_sender, watch_option);
-
-            let result = engine::run(
+            let trace_io_option = arg_matches.is_present(cli::arg::TRACE_IO).into();
+            let notifier = build_notifier(&arg_matches, &root_project_dir);
+            run_targets(
+                targets,
                 root_target_ids,
                 watch_option,
-                &mut target_actors,
-                termination_events,
-                target_actor_output_events,
+                trace_io_option,
+                notifier,
             )
-            .await;
+            .await?;
+        }
+
+        Ok(())
+    })
+}
+
+async fn run_targets(
+    targets: HashMap<TargetId, Target>,
+    root_target_ids: Vec<TargetId>,
+    watch_option: WatchOption,
+    trace_io_option: TraceIoOption,
+    notifier: Notifier,
+) -> Result<()> {
+    let termination_events = terminate_on_ctrlc()?;
+
+    let (target_actor_output_sender, target_actor_output_events) =
+        channel::bounded(crate::DEFAULT_CHANNEL_CAP);
+    let mut target_actors = TargetActors::new(
+        targets,
+        &root_target_ids,
+        target_actor_output_sender,
+        watch_option,
+        trace_io_option,
+    )?;
+
+    let result = engine::run(
+        root_target_ids,
+        watch_option,
+        notifier,
+        &mut target_actors,
+        termination_events,
+        target_actor_output_events,
+    )
+    .await;
+
+    target_actors.terminate().await;
+
+    result
+}
+
+fn run_affected(
+    arg_matches: &clap::ArgMatches,
+    config: ir::Config,
+    root_project_dir: &std::path::Path,
+    project_dirs: &[std::path::PathBuf],
+) -> Result<()> {
+    let candidate_ids = match arg_matches.values_of_lossy(cli::arg::TARGETS) {
+        Some(requested_targets) => config.select_targets(&requested_targets)?,
+        None => config.list_all_targets(),
+    };
+    let targets = config.try_into_domain_targets(&candidate_ids)?;
+    let since = arg_matches.value_of(cli::arg::SINCE).unwrap();
 
-            target_actors.terminate().await;
+    task::block_on(async {
+        let changed_files = affected::list_changed_files(root_project_dir.into(), since).await?;
+        let affected_target_ids =
+            affected::find_affected_targets(&targets, &candidate_ids, &changed_files);
 
-            result?;
+        if !arg_matches.is_present(cli::arg::RUN) {
+            for target_id in &affected_target_ids {
+                println!("{}", target_id);
+            }
+        } else if affected_target_ids.is_empty() {
+            log::info!("No target affected by the changes since {}", since);
+        } else {
+            let _work_dir_locks = lock_work_dirs(project_dirs, arg_matches).await?;
+            let watch_option = arg_matches.is_present(cli::arg::WATCH).into();
+            let notifier = Notifier::new(vec![], root_project_dir.into());
+            run_targets(
+                targets,
+                affected_target_ids,
+                watch_option,
+                TraceIoOption::Disabled,
+                notifier,
+            )
+            .await?;
         }
 
         Ok(())
     })
 }
 
+fn run_state(arg_matches: &clap::ArgMatches, config: ir::Config) -> Result<()> {
+    let pattern = arg_matches.value_of(cli::arg::TARGET).unwrap();
+
+    let target_ids = config.select_targets(&[pattern.to_string()])?;
+    if target_ids.len() > 1 {
+        return Err(anyhow!("{} matches several targets", pattern));
+    }
+    let mut targets = config.try_into_domain_targets(&target_ids)?;
+    let target = match targets.remove(&target_ids[0]) {
+        Some(Target::Build(target)) => target,
+        _ => return Err(anyhow!("Target {} is not a build target", target_ids[0])),
+    };
+
+    task::block_on(async {
+        let json = if arg_matches.value_of(cli::arg::STATE_ACTION) == Some(cli::state_action::SHOW)
+        {
+            serde_json::to_string_pretty(&report::show_state(&target).await?)?
+        } else {
+            serde_json::to_string_pretty(&report::diff_state(&target).await?)?
+        };
+        println!("{}", json);
+
+        Ok(())
+    })
+}
+
+async fn lock_work_dirs(
+    project_dirs: &[std::path::PathBuf],
+    arg_matches: &clap::ArgMatches,
+) -> Result<WorkDirLocks> {
+    let lock_policy = arg_matches
+        .value_of(cli::arg::LOCK)
+        .and_then(LockPolicy::try_parse)
+        .unwrap();
+    let project_dirs = project_dirs
+        .iter()
+        .cloned()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+
+    work_dir::lock_work_dirs(&project_dirs, lock_policy).await
+}
+
+fn build_notifier(arg_matches: &clap::ArgMatches, root_project_dir: &std::path::Path) -> Notifier {
+    let mut methods: Vec<_> = arg_matches
+        .values_of(cli::arg::NOTIFY)
+        .map(|values| values.flat_map(NotificationMethod::try_parse).collect())
+        .unwrap_or_default();
+
+    if let Some(cmd) = arg_matches.value_of(cli::arg::NOTIFY_COMMAND) {
+        methods.push(NotificationMethod::Command(cmd.to_string()));
+    }
+
+    Notifier::new(methods, root_project_dir.into())
+}
+
 fn terminate_on_ctrlc() -> Result<Receiver<TerminationMessage>> {
     let (termination_sender, termination_events) = channel::bounded(1);
-    let ctrlc = CtrlC::new().with_context(|| "Failed to set Ctrl-C handler")?;
+    let mut ctrlc = CtrlC::new().with_context(|| "Failed to set Ctrl-C handler")?;
 
+    // Every signal is forwarded, as a second Ctrl-C interrupts the finalizers
     task::spawn(async move {
-        ctrlc.await;
-        log::debug!("Ctrl-C received");
-        let _ = termination_sender.send(TerminationMessage).await;
+        loop {
+            (&mut ctrlc).await;
+            log::debug!("Ctrl-C received");
+            if termination_sender.send(TerminationMessage).await.is_err() {
+                break;
+            }
+        }
     });
 
     Ok(termination_events)
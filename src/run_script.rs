@@ -1,16 +1,119 @@
+use crate::domain::{Script, Shell};
+use anyhow::{Context, Result};
 use async_process::Command;
-use async_std::path::Path;
+use async_std::path::{Path, PathBuf};
+use std::ffi::OsString;
 
 pub fn build_command(script: &str, dir: &Path) -> Command {
-    let (program, run_arg) = if cfg!(windows) {
-        let comspec = std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd.exe".into());
-        (comspec, "/C")
-    } else {
-        ("/bin/sh".into(), "-ce")
-    };
+    build_shell_command(None, script, dir)
+}
+
+/// Builds the command running the script of a target.
+///
+/// Exec form scripts are spawned directly, without any shell.
+pub fn build_script_command(script: &Script, shell: Option<&Shell>, dir: &Path) -> Command {
+    match script {
+        Script::Shell(script) => build_shell_command(shell, script, dir),
+        Script::Exec(args) => {
+            let mut command = Command::new(&args[0]);
+            command.args(&args[1..]).current_dir(dir);
+            command
+        }
+    }
+}
+
+/// Returns the program running the script of a target, followed by its arguments.
+///
+/// It allows wrapping the script in another program, such as a tracer or a sandbox.
+pub fn script_command_line(script: &Script, shell: Option<&Shell>) -> Vec<OsString> {
+    match script {
+        Script::Shell(script) => {
+            let (program, args) = shell_program_and_args(shell);
+            let mut command_line = vec![program];
+            command_line.extend(args.into_iter().map(OsString::from));
+            command_line.push(script.into());
+            command_line
+        }
+        Script::Exec(args) => args.iter().map(OsString::from).collect(),
+    }
+}
 
+/// Builds the command running a command line, as returned by [`script_command_line`].
+pub fn build_command_line_command(command_line: &[OsString], dir: &Path) -> Command {
+    let mut command = Command::new(&command_line[0]);
+    command.args(&command_line[1..]).current_dir(dir);
+    command
+}
+
+/// Builds the command running the script with the provided shell,
+/// or with the default shell of the platform if none is provided.
+pub fn build_shell_command(shell: Option<&Shell>, script: &str, dir: &Path) -> Command {
+    let (program, args) = shell_program_and_args(shell);
     let mut command = Command::new(program);
-    command.arg(run_arg).arg(script).current_dir(dir);
+    command.args(args).arg(script).current_dir(dir);
 
     command
 }
+
+/// Returns the program and arguments of the shell, to which the script is passed as a last argument.
+fn shell_program_and_args(shell: Option<&Shell>) -> (OsString, Vec<String>) {
+    match shell {
+        Some(shell) => (shell.program.clone().into(), shell.args.clone()),
+        None if cfg!(windows) => {
+            let comspec = std::env::var_os("COMSPEC").unwrap_or_else(|| "cmd.exe".into());
+            (comspec, vec!["/C".to_string()])
+        }
+        None => ("/bin/sh".into(), vec!["-ce".to_string()]),
+    }
+}
+
+/// Loads the variables of the dotenv files in the environment of the command.
+///
+/// Missing files are ignored.
+pub fn load_env_files(command: &mut Command, env_files: &[PathBuf]) -> Result<()> {
+    for env_file in env_files {
+        let env_file: &std::path::Path = env_file.as_path().into();
+        if !env_file.is_file() {
+            continue;
+        }
+
+        let variables = dotenvy::from_path_iter(env_file)
+            .with_context(|| format!("Failed to read env file {}", env_file.display()))?;
+        for variable in variables {
+            let (key, value) =
+                variable.with_context(|| format!("Invalid env file {}", env_file.display()))?;
+            command.env(key, value);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the flag expected by an interpreter before an inline script.
+pub fn script_flag(program: &str) -> &'static str {
+    let program_name = Path::new(program)
+        .file_stem()
+        .map(|file_stem| file_stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    match program_name.as_str() {
+        "cmd" => "/C",
+        "powershell" | "pwsh" => "-Command",
+        "node" | "perl" | "ruby" => "-e",
+        _ => "-c",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::script_flag;
+
+    #[test]
+    fn test_script_flag() {
+        assert_eq!(script_flag("bash"), "-c");
+        assert_eq!(script_flag("/usr/bin/python3"), "-c");
+        assert_eq!(script_flag("node"), "-e");
+        assert_eq!(script_flag("cmd.exe"), "/C");
+        assert_eq!(script_flag("pwsh"), "-Command");
+    }
+}
@@ -0,0 +1,36 @@
+/// Selection of targets based on their tags.
+#[derive(Debug, Default)]
+pub struct TagFilter {
+    pub included_tags: Vec<String>,
+    pub excluded_tags: Vec<String>,
+}
+
+impl TagFilter {
+    pub fn is_empty(&self) -> bool {
+        self.included_tags.is_empty() && self.excluded_tags.is_empty()
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        (self.included_tags.is_empty() || self.included_tags.iter().any(|tag| tags.contains(tag)))
+            && !self.excluded_tags.iter().any(|tag| tags.contains(tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TagFilter;
+
+    #[test]
+    fn test_tag_filter_matches() {
+        let tag_filter = TagFilter {
+            included_tags: vec!["ci".to_string()],
+            excluded_tags: vec!["slow".to_string()],
+        };
+        let tags = |tags: Vec<&str>| tags.into_iter().map(str::to_string).collect::<Vec<_>>();
+
+        assert!(tag_filter.matches(&tags(vec!["ci"])));
+        assert!(!tag_filter.matches(&tags(vec!["ci", "slow"])));
+        assert!(!tag_filter.matches(&tags(vec![])));
+        assert!(TagFilter::default().matches(&tags(vec![])));
+    }
+}
@@ -1,11 +1,20 @@
-use anyhow::{Error, Result};
+use anyhow::{anyhow, Context, Error, Result};
 use async_std::fs;
 use async_std::path::{self, Path, PathBuf};
-use std::io::ErrorKind;
+use async_std::prelude::*;
+use async_std::task;
+use fs2::FileExt;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io::{ErrorKind, Write};
+use std::sync::Mutex;
 
 /// Name of the directory in which Žinoma stores its own files.
 const WORK_DIR_NAME: &str = ".zinoma";
 
+/// Name of the file locked by the process writing to the work directory.
+const LOCK_FILE_NAME: &str = "lock";
+
 pub fn is_in_work_dir(path: &Path) -> bool {
     path.components().any(|component| match component {
         path::Component::Normal(name) => name == WORK_DIR_NAME,
@@ -17,22 +26,202 @@ pub fn get_work_dir_path(project_dir: &Path) -> PathBuf {
     project_dir.join(WORK_DIR_NAME)
 }
 
+/// Removes the files of the work directory, except its lock.
 pub async fn remove_work_dir(project_dir: &Path) -> Result<()> {
-    let checksums_dir = get_work_dir_path(project_dir);
-    match fs::remove_dir_all(&checksums_dir).await {
-        Ok(_) => {}
-        Err(e) if e.kind() == ErrorKind::NotFound => {}
+    let work_dir = get_work_dir_path(project_dir);
+    let mut entries = match fs::read_dir(&work_dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
         Err(e) => {
             return Err(Error::new(e).context(format!(
-                "Failed to remove checksums directory {}",
-                checksums_dir.display()
+                "Failed to read checksums directory {}",
+                work_dir.display()
             )));
         }
+    };
+
+    while let Some(entry) = entries.next().await {
+        let entry = entry.with_context(|| {
+            format!("Failed to read checksums directory {}", work_dir.display())
+        })?;
+
+        // The lock file is kept, as other processes could otherwise lock a new one
+        if entry.file_name() == LOCK_FILE_NAME {
+            continue;
+        }
+
+        let path = entry.path();
+        let result = if path.is_dir().await {
+            fs::remove_dir_all(&path).await
+        } else {
+            fs::remove_file(&path).await
+        };
+        result.with_context(|| format!("Failed to remove {}", path.display()))?;
     }
 
     Ok(())
 }
 
+/// Writes a file of the work directory through a temporary file, renamed once complete.
+///
+/// This way, neither a crash nor a concurrent read can observe a partially written file.
+pub fn write_atomically<F>(file_path: &std::path::Path, write: F) -> Result<()>
+where
+    F: FnOnce(&mut std::fs::File) -> Result<()>,
+{
+    let tmp_file_path = file_path.with_extension(format!("tmp.{}", std::process::id()));
+
+    let result = std::fs::File::create(&tmp_file_path)
+        .with_context(|| format!("Failed to create {}", tmp_file_path.display()))
+        .and_then(|mut tmp_file| {
+            write(&mut tmp_file)?;
+            tmp_file
+                .sync_all()
+                .with_context(|| format!("Failed to write {}", tmp_file_path.display()))
+        })
+        .and_then(|_| {
+            std::fs::rename(&tmp_file_path, file_path)
+                .with_context(|| format!("Failed to write {}", file_path.display()))
+        });
+
+    if result.is_err() {
+        std::fs::remove_file(&tmp_file_path).ok();
+    }
+
+    result
+}
+
+/// Policy applied when the work directory of a project is locked by another process.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LockPolicy {
+    /// Wait for the other process to release the lock.
+    Wait,
+    /// Fail immediately.
+    Fail,
+    /// Proceed without writing to the work directory.
+    ReadOnly,
+}
+
+impl LockPolicy {
+    pub fn try_parse(policy: &str) -> Option<Self> {
+        match policy {
+            "wait" => Some(Self::Wait),
+            "fail" => Some(Self::Fail),
+            "readonly" => Some(Self::ReadOnly),
+            _ => None,
+        }
+    }
+}
+
+lazy_static! {
+    /// Projects whose work directory should not be written to, as another process holds its lock.
+    static ref READ_ONLY_PROJECT_DIRS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+pub fn is_read_only(project_dir: &Path) -> bool {
+    READ_ONLY_PROJECT_DIRS.lock().unwrap().contains(project_dir)
+}
+
+/// Advisory locks on the work directories of the projects, released when dropped.
+pub struct WorkDirLocks {
+    _lock_files: Vec<std::fs::File>,
+}
+
+/// Locks the work directories of the projects, so that a single `zinoma` process writes to them at a time.
+///
+/// The lock file contains the PID of the process holding it.
+pub async fn lock_work_dirs(project_dirs: &[PathBuf], policy: LockPolicy) -> Result<WorkDirLocks> {
+    let mut project_dirs = project_dirs.to_vec();
+    // Locks are always acquired in the same order, to prevent deadlocks between processes
+    project_dirs.sort();
+
+    let mut lock_files = Vec::with_capacity(project_dirs.len());
+    for project_dir in project_dirs {
+        let lock_file_path = get_work_dir_path(&project_dir).join(LOCK_FILE_NAME);
+        let lock_file =
+            task::spawn_blocking(move || lock_work_dir(&lock_file_path, policy)).await?;
+        match lock_file {
+            Some(lock_file) => lock_files.push(lock_file),
+            None => {
+                READ_ONLY_PROJECT_DIRS.lock().unwrap().insert(project_dir);
+            }
+        }
+    }
+
+    Ok(WorkDirLocks {
+        _lock_files: lock_files,
+    })
+}
+
+fn lock_work_dir(lock_file_path: &Path, policy: LockPolicy) -> Result<Option<std::fs::File>> {
+    let lock_file_path: &std::path::Path = lock_file_path.into();
+    if let Some(work_dir) = lock_file_path.parent() {
+        std::fs::create_dir_all(work_dir)
+            .with_context(|| format!("Failed to create work directory {}", work_dir.display()))?;
+    }
+
+    let mut lock_file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_file_path)
+        .with_context(|| format!("Failed to open lock file {}", lock_file_path.display()))?;
+
+    if let Err(e) = lock_file.try_lock_exclusive() {
+        if e.kind() != fs2::lock_contended_error().kind() {
+            return Err(
+                Error::new(e).context(format!("Failed to lock {}", lock_file_path.display()))
+            );
+        }
+
+        let pid = read_lock_holder_pid(lock_file_path);
+        match policy {
+            LockPolicy::Fail => {
+                return Err(anyhow!(
+                    "{} is locked by process {}",
+                    lock_file_path.display(),
+                    pid
+                ));
+            }
+            LockPolicy::ReadOnly => {
+                log::warn!(
+                    "{} is locked by process {}. The incremental state will not be saved.",
+                    lock_file_path.display(),
+                    pid
+                );
+                return Ok(None);
+            }
+            LockPolicy::Wait => {
+                log::info!(
+                    "Waiting for process {} to release {}",
+                    pid,
+                    lock_file_path.display()
+                );
+                lock_file
+                    .lock_exclusive()
+                    .with_context(|| format!("Failed to lock {}", lock_file_path.display()))?;
+            }
+        }
+    }
+
+    lock_file
+        .set_len(0)
+        .and_then(|_| write!(lock_file, "{}", std::process::id()))
+        .and_then(|_| lock_file.flush())
+        .with_context(|| format!("Failed to write lock file {}", lock_file_path.display()))?;
+
+    Ok(Some(lock_file))
+}
+
+fn read_lock_holder_pid(lock_file_path: &std::path::Path) -> String {
+    std::fs::read_to_string(lock_file_path)
+        .ok()
+        .map(|content| content.trim().to_string())
+        .filter(|pid| !pid.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
 pub fn is_work_dir(entry: &walkdir::DirEntry) -> bool {
     entry
         .file_name()
@@ -43,8 +232,9 @@ pub fn is_work_dir(entry: &walkdir::DirEntry) -> bool {
 
 #[cfg(test)]
 mod tests {
-    use super::is_in_work_dir;
-    use async_std::path::Path;
+    use super::{is_in_work_dir, is_read_only, lock_work_dirs, LockPolicy};
+    use async_std::path::{Path, PathBuf};
+    use async_std::task;
 
     #[test]
     fn test_is_in_work_dir() {
@@ -54,4 +244,30 @@ mod tests {
         )));
         assert!(!is_in_work_dir(Path::new("/my/file.json")));
     }
+
+    #[test]
+    fn test_lock_work_dirs_read_only_per_project() {
+        task::block_on(async {
+            let tmp_dir = PathBuf::from(std::env::temp_dir())
+                .join(format!("zinoma-work-dir-lock-{}", std::process::id()));
+            let locked_project_dir = tmp_dir.join("locked");
+            let free_project_dir = tmp_dir.join("free");
+
+            let _locks =
+                lock_work_dirs(std::slice::from_ref(&locked_project_dir), LockPolicy::Fail)
+                    .await
+                    .unwrap();
+            let _read_only_locks = lock_work_dirs(
+                &[locked_project_dir.clone(), free_project_dir.clone()],
+                LockPolicy::ReadOnly,
+            )
+            .await
+            .unwrap();
+
+            assert!(is_read_only(&locked_project_dir));
+            assert!(!is_read_only(&free_project_dir));
+
+            std::fs::remove_dir_all(&tmp_dir).unwrap();
+        })
+    }
 }
@@ -3,6 +3,8 @@ use predicate::str::contains;
 use predicates::prelude::*;
 use std::ffi;
 use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::process::Stdio;
 use std::{path::Path, process::Command};
 
 #[test]
@@ -57,6 +59,216 @@ fn invalid_project_name() {
         .stderr(contains(":::: is not a valid project name"));
 }
 
+#[test]
+fn workspaces() {
+    zinoma_command("workspaces", ["test_all"])
+        .assert()
+        .success()
+        .stdout(contains("Testing api"))
+        .stdout(contains("Testing webapp"));
+}
+
+#[test]
+fn target_patterns() {
+    zinoma_command("workspaces", ["*::test"])
+        .assert()
+        .success()
+        .stdout(contains("Testing api"))
+        .stdout(contains("Testing webapp"));
+
+    zinoma_command("workspaces", ["*::lint"])
+        .assert()
+        .failure()
+        .stderr(contains("No target matches *::lint"));
+}
+
+#[test]
+fn tags() {
+    zinoma_command("tags", ["--tag", "ci", "--exclude-tag", "slow"])
+        .assert()
+        .success()
+        .stdout(contains("Linting"))
+        .stdout(contains("Reporting"))
+        .stdout(contains("Testing frontend"))
+        .stdout(contains("Running e2e tests").not());
+
+    zinoma_command("tags", ["@slow"])
+        .assert()
+        .success()
+        .stdout(contains("Installing"))
+        .stdout(contains("Running e2e tests"))
+        .stdout(contains("Linting").not());
+
+    zinoma_command("tags", ["--tag", "release"])
+        .assert()
+        .failure()
+        .stderr(contains("No target matches the tag filters"));
+}
+
+#[test]
+fn when() {
+    zinoma_command("when", ["all"])
+        .env_remove("ZINOMA_TEST_CI")
+        .assert()
+        .success()
+        .stdout(contains("Dependencies are OK"))
+        .stdout(contains("Running").not())
+        .stderr(contains("on_ci - Build skipped (condition)"))
+        .stderr(contains("on_plan9 - Build skipped (condition)"))
+        .stderr(contains("with_missing_tool - Build skipped (condition)"));
+
+    zinoma_command("when", ["on_ci"])
+        .env("ZINOMA_TEST_CI", "true")
+        .assert()
+        .success()
+        .stdout(contains("Running on CI"));
+}
+
+#[test]
+fn after_and_optional_dependencies() {
+    zinoma_command("after_and_optional_dependencies", ["lint"])
+        .assert()
+        .success()
+        .stdout(contains("Linting"))
+        .stdout(contains("Formatting").not());
+
+    zinoma_command("after_and_optional_dependencies", ["lint", "fmt"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("Formatting\\n(.|\\n)*Linting").unwrap());
+
+    zinoma_command("after_and_optional_dependencies", ["coverage"])
+        .assert()
+        .success()
+        .stdout(contains("Reporting coverage"))
+        .stderr(contains("WARN failing - Build failed"));
+
+    zinoma_command("after_and_optional_dependencies", ["release"])
+        .assert()
+        .failure()
+        .stderr(contains("An issue occurred with target failing"))
+        .stdout(contains("Releasing").not());
+}
+
+#[test]
+fn finally() {
+    zinoma_command("finally", ["passing_tests"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("Tests passed\\n(.|\\n)*Tearing down").unwrap());
+
+    zinoma_command("finally", ["failing_tests"])
+        .assert()
+        .failure()
+        .stdout(contains("Tearing down"))
+        .stderr(contains("An issue occurred with target failing_tests"));
+
+    zinoma_command("finally", ["with_failing_finalizer"])
+        .assert()
+        .failure()
+        .stderr(contains("WARN failing_teardown - Finalizer failed"))
+        .stderr(contains("An issue occurred with target failing_teardown"));
+}
+
+#[test]
+fn finally_interrupted() {
+    let mut build = zinoma_command("finally", ["interrupted"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut build_stderr = BufReader::new(build.stderr.take().unwrap())
+        .lines()
+        .map(Result::unwrap);
+    let interrupt = || {
+        let status = Command::new("kill")
+            .args(["-INT", &build.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    assert!(build_stderr.any(|line| line.contains("interrupted - Building")));
+    interrupt();
+    assert!(build_stderr.any(|line| line.contains("slow_teardown - Building")));
+
+    // A second Ctrl-C stops the finalizers instead of waiting for them
+    interrupt();
+    assert!(build_stderr.any(|line| line.contains("Interrupted finalizers slow_teardown")));
+    assert!(!build.wait().unwrap().success());
+}
+
+#[test]
+fn shell() {
+    zinoma_command("shell", ["bash_default", "sh_override", "python"])
+        .assert()
+        .success()
+        .stdout(contains("Running bash"))
+        .stdout(contains("Running sh"))
+        .stdout(contains("Sum is 6"));
+
+    // Conditions run with the shell of the target as well
+    zinoma_command("shell", ["bash_condition"])
+        .assert()
+        .success()
+        .stdout(contains("Bash condition met"));
+
+    zinoma_command("shell", ["failing_pipe"])
+        .assert()
+        .failure()
+        .stderr(contains("An issue occurred with target failing_pipe"));
+}
+
+#[test]
+fn workdir() {
+    zinoma_command("workdir", ["read_marker", "print_marker"])
+        .assert()
+        .success()
+        .stdout(contains("frontend marker"));
+
+    // The working directories of all the targets are checked, even if they are not selected
+    zinoma_command("workdir_missing", ["valid"])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid target missing_workdir"))
+        .stderr(contains("does not exist"))
+        .stdout(contains("Should not run").not());
+
+    zinoma_command("workdir_outside", ["valid"])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid target outside_workdir"))
+        .stderr(contains(
+            "Working directory ../.. is outside of the project directory",
+        ))
+        .stdout(contains("Should not run").not());
+}
+
+#[test]
+fn exec_form() {
+    zinoma_command("exec_form", ["no_shell_expansion", "with_variables"])
+        .assert()
+        .success()
+        .stdout(contains("$HOME && exit 1"))
+        .stdout(contains("Hello from with_variables"));
+
+    zinoma_command("exec_form", ["missing_program"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Failed to spawn build command for missing_program",
+        ));
+}
+
+#[test]
+fn workspaces_duplicate_names() {
+    zinoma_command("workspaces_duplicate_names", ["--clean"])
+        .assert()
+        .failure()
+        .stderr(contains("Project name duplicate is used by both"))
+        .stderr(contains("packages/a/zinoma.yml"))
+        .stderr(contains("packages/b/zinoma.yml"));
+}
+
 #[test]
 fn root_input_path() {
     zinoma_command("root_input_path", ["--clean", "print_source"])
@@ -285,6 +497,304 @@ fn paths_output_resource_with_extensions() {
         .stderr(contains("cp_in_to_out - Build success"));
 }
 
+#[test]
+fn matrix() {
+    zinoma_command("matrix", ["print_greetings"])
+        .assert()
+        .success()
+        .stdout(contains("Greeting in en"))
+        .stdout(contains("Greeting in fr"))
+        .stdout(contains("Greeted in en"))
+        .stdout(contains("Greeted in fr"));
+
+    zinoma_command("matrix", ["greet[language=fr]"])
+        .assert()
+        .success()
+        .stdout(contains("Greeting in fr"))
+        .stdout(contains("Greeting in en").not());
+}
+
+#[test]
+fn variables() {
+    zinoma_command("variables", ["greet"])
+        .assert()
+        .success()
+        .stdout(contains("Hello from greet"));
+
+    zinoma_command("variables", ["shell_variable"])
+        .assert()
+        .success()
+        .stdout(contains("Hi from the shell"));
+
+    zinoma_command("variables", ["undefined_env_variable"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Failed to interpolate variables of target undefined_env_variable",
+        ))
+        .stderr(contains(
+            "Environment variable ZINOMA_UNDEFINED_VARIABLE is not defined",
+        ));
+
+    zinoma_command("variables", ["unknown_variable"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Failed to interpolate variables of target unknown_variable",
+        ))
+        .stderr(contains("zinoma.yml"))
+        .stderr(contains("Unknown variable src_dir"));
+}
+
+#[test]
+fn templates() {
+    zinoma_command("templates", ["inherited"])
+        .assert()
+        .success()
+        .stdout(contains("Hello from the template"));
+
+    zinoma_command("templates", ["overridden"])
+        .assert()
+        .success()
+        .stdout(contains("Preparing"))
+        .stdout(contains("Hello from the target"))
+        .stdout(contains("Hello from the local template").not());
+}
+
+#[test]
+fn env_file() {
+    zinoma_command("env_file", ["--clean", "print_project_env"])
+        .assert()
+        .success()
+        .stdout(contains("greeting=Hello world"));
+
+    // Targets without declared input always run, whatever their env files
+    zinoma_command("env_file", ["print_project_env"])
+        .assert()
+        .success()
+        .stdout(contains("greeting=Hello world"));
+
+    zinoma_command("env_file", ["--clean", "print_target_env"])
+        .assert()
+        .success()
+        .stdout(contains("greeting=Bonjour, message=Bonjour le monde"));
+
+    zinoma_command("env_file", ["--clean", "missing_env_file"])
+        .assert()
+        .success()
+        .stdout(contains("greeting=Hello world"));
+}
+
+#[test]
+fn hash_algorithm() {
+    zinoma_command("hash_algorithm", ["--clean", "print_source"])
+        .assert()
+        .success()
+        .stdout(contains("sha256 source"));
+
+    zinoma_command("hash_algorithm", ["print_source"])
+        .assert()
+        .success()
+        .stderr(contains("Build skipped (Not Modified)"));
+}
+
+#[test]
+fn state() {
+    zinoma_command("state", ["--clean", "print_source"])
+        .assert()
+        .success();
+
+    zinoma_command("state", ["state", "show", "print_source"])
+        .assert()
+        .success()
+        .stdout(contains(r#""format_version": 1"#))
+        .stdout(contains(r#""hash_algorithm": "blake3""#))
+        .stdout(contains("source.txt"))
+        .stdout(contains(r#""echo \"stable output\"": "stable output\n""#));
+
+    zinoma_command("state", ["state", "diff", "print_source"])
+        .assert()
+        .success()
+        .stdout(contains(r#""up_to_date": true"#));
+
+    zinoma_command("state", ["state", "show", "never_built"])
+        .assert()
+        .failure()
+        .stderr(contains("No state is recorded for target never_built"));
+}
+
+#[test]
+fn lock() {
+    let checksums_file = Path::new("tests/integ/lock/.zinoma/print_source.checksums");
+    fs::remove_file(checksums_file).ok();
+
+    let mut lock_holder = zinoma_command("lock", ["hold_lock"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let lock_holder_pid = lock_holder.id().to_string();
+    while fs::read_to_string("tests/integ/lock/.zinoma/lock").ok() != Some(lock_holder_pid.clone())
+    {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    zinoma_command("lock", ["--lock", "fail", "print_source"])
+        .assert()
+        .failure()
+        .stderr(contains(format!(
+            "is locked by process {}",
+            lock_holder_pid
+        )));
+
+    zinoma_command("lock", ["--lock", "readonly", "print_source"])
+        .assert()
+        .success()
+        .stdout(contains("lock source"))
+        .stderr(contains("The incremental state will not be saved"));
+    assert!(!checksums_file.exists());
+
+    let mut waiting_build = zinoma_command("lock", ["--lock", "wait", "print_source"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut waiting_build_stderr = BufReader::new(waiting_build.stderr.take().unwrap())
+        .lines()
+        .map(Result::unwrap);
+    let waiting_message = format!("Waiting for process {}", lock_holder_pid);
+    assert!(waiting_build_stderr.any(|line| line.contains(&waiting_message)));
+
+    // The lock is only released once the waiting build is blocked on it
+    writeln!(lock_holder.stdin.take().unwrap()).unwrap();
+    assert!(lock_holder.wait().unwrap().success());
+
+    assert!(waiting_build_stderr.any(|line| line.contains("print_source - Build success")));
+    assert!(waiting_build.wait().unwrap().success());
+    assert!(checksums_file.exists());
+}
+
+#[test]
+fn run_if_missing() {
+    zinoma_command("run_if_missing", ["--clean", "generate"])
+        .assert()
+        .success()
+        .stdout(contains("Generating"))
+        .stderr(contains("generate - Build success"));
+
+    zinoma_command("run_if_missing", ["generate"])
+        .assert()
+        .success()
+        .stdout(contains("Generating").not())
+        .stderr(contains("generate - Build skipped"));
+
+    fs::remove_file("tests/integ/run_if_missing/generated/file").unwrap();
+
+    zinoma_command("run_if_missing", ["generate"])
+        .assert()
+        .success()
+        .stdout(contains("Generating"));
+}
+
+#[test]
+fn required_output() {
+    zinoma_command("required_output", ["missing_output"])
+        .assert()
+        .failure()
+        .stderr(contains("Build did not produce its output"));
+
+    zinoma_command("required_output", ["optional_output"])
+        .assert()
+        .success();
+
+    zinoma_command("required_output", ["empty_output"])
+        .assert()
+        .failure()
+        .stderr(contains("Build produced an empty output"));
+}
+
+#[test]
+fn clean_before_build() {
+    let stale_file = Path::new("tests/integ/clean_before_build/dist/stale.txt");
+    fs::create_dir_all(stale_file.parent().unwrap()).unwrap();
+    fs::write(stale_file, "").unwrap();
+
+    zinoma_command("clean_before_build", ["build"])
+        .assert()
+        .success()
+        .stderr(contains("build - Build success"));
+
+    assert!(!stale_file.exists());
+    assert!(Path::new("tests/integ/clean_before_build/dist/fresh.txt").exists());
+}
+
+#[test]
+fn affected() {
+    let repository_dir =
+        std::env::temp_dir().join(format!("zinoma-affected-{}", std::process::id()));
+    fs::create_dir_all(repository_dir.join("api")).unwrap();
+    fs::create_dir_all(repository_dir.join("webapp")).unwrap();
+    fs::write(repository_dir.join("api/main.rs"), "fn main() {}").unwrap();
+    fs::write(repository_dir.join("webapp/index.js"), "").unwrap();
+    fs::write(
+        repository_dir.join("zinoma.yml"),
+        r#"targets:
+  api_build:
+    input:
+      - paths: [api]
+    build: echo Building api
+  api_test:
+    dependencies: [api_build]
+    after: [webapp_test]
+    build: echo Testing api
+  webapp_test:
+    input:
+      - paths: [webapp]
+    build: echo Testing webapp
+"#,
+    )
+    .unwrap();
+
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args([
+                "-c",
+                "user.name=zinoma",
+                "-c",
+                "user.email=zinoma@example.com",
+            ])
+            .args(args)
+            .current_dir(&repository_dir)
+            .assert()
+            .success();
+    };
+    git(&["init", "--quiet"]);
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "Initial commit"]);
+
+    fs::write(repository_dir.join("api/main.rs"), "fn main() { todo!() }").unwrap();
+
+    Command::cargo_bin("zinoma")
+        .unwrap()
+        .args(["affected", "--since", "HEAD"])
+        .current_dir(&repository_dir)
+        .assert()
+        .success()
+        .stdout(contains("api_build"))
+        .stdout(contains("api_test"))
+        .stdout(contains("webapp_test").not());
+
+    Command::cargo_bin("zinoma")
+        .unwrap()
+        .arg("-p")
+        .arg(&repository_dir)
+        .args(["affected", "--since", "HEAD", "--run"])
+        .assert()
+        .success()
+        .stdout(contains("Testing api"))
+        .stdout(contains("Testing webapp").not());
+
+    fs::remove_dir_all(&repository_dir).unwrap();
+}
+
 fn zinoma_command<I, S>(integ_test_dir_name: &str, args: I) -> Command
 where
     I: IntoIterator<Item = S>,